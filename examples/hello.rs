@@ -116,5 +116,7 @@ async fn main() {
         .iter()
         .map(|s| OsString::from(&s))
         .collect::<Vec<OsString>>();
-    async_fuse::mount(HelloFS, mountpoint, &options).unwrap();
+    async_fuse::mount(HelloFS, mountpoint, &options)
+        .await
+        .unwrap();
 }
@@ -0,0 +1,247 @@
+//! A single in-memory file with the kernel's writeback cache enabled.
+//!
+//! In writeback cache mode, the kernel buffers writes and owns the size, mtime and ctime of
+//! cached files: it sends them with setattr, and they are stored as given. Delayed writes
+//! from the page cache use a guessed file handle, so writes must not depend on it.
+
+use async_fuse::consts::{FUSE_WRITEBACK_CACHE, FUSE_WRITE_CACHE};
+use async_fuse::{
+    FileAttr, FileType, Filesystem, KernelConfig, OpenOptions, ReplyAttr, ReplyData,
+    ReplyDirectory, ReplyEntry, ReplyOpen, ReplyWrite, Request, TimeOrNow,
+};
+use async_trait::async_trait;
+use libc::{c_int, ENOENT};
+use log::debug;
+use std::env;
+use std::ffi::{OsStr, OsString};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const TTL: Duration = Duration::from_secs(1); // 1 second
+
+const LOG_DIR_ATTR: FileAttr = FileAttr {
+    ino: 1,
+    size: 0,
+    blocks: 0,
+    atime: UNIX_EPOCH, // 1970-01-01 00:00:00
+    mtime: UNIX_EPOCH,
+    ctime: UNIX_EPOCH,
+    crtime: UNIX_EPOCH,
+    kind: FileType::Directory,
+    perm: 0o755,
+    nlink: 2,
+    uid: 501,
+    gid: 20,
+    rdev: 0,
+    flags: 0,
+};
+
+struct LogFile {
+    data: Vec<u8>,
+    attr: FileAttr,
+}
+
+struct WritebackFS {
+    file: Mutex<LogFile>,
+}
+
+impl WritebackFS {
+    fn new() -> Self {
+        let now = SystemTime::now();
+        WritebackFS {
+            file: Mutex::new(LogFile {
+                data: Vec::new(),
+                attr: FileAttr {
+                    ino: 2,
+                    size: 0,
+                    blocks: 0,
+                    atime: now,
+                    mtime: now,
+                    ctime: now,
+                    crtime: now,
+                    kind: FileType::RegularFile,
+                    perm: 0o644,
+                    nlink: 1,
+                    uid: 501,
+                    gid: 20,
+                    rdev: 0,
+                    flags: 0,
+                },
+            }),
+        }
+    }
+}
+
+#[async_trait]
+impl Filesystem for WritebackFS {
+    async fn init(&self, _req: &Request, config: &mut KernelConfig) -> Result<(), c_int> {
+        // Let the kernel cache writes if it supports that
+        if config.add_capabilities(FUSE_WRITEBACK_CACHE).is_err() {
+            debug!("Writeback cache not supported by the kernel");
+        }
+        Ok(())
+    }
+
+    async fn lookup(&self, _req: &Request, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        if parent == 1 && name.to_str() == Some("log.txt") {
+            reply.entry(&TTL, &self.file.lock().unwrap().attr, 0);
+        } else {
+            reply.error(ENOENT);
+        }
+    }
+
+    async fn getattr(&self, _req: &Request, ino: u64, reply: ReplyAttr) {
+        match ino {
+            1 => reply.attr(&TTL, &LOG_DIR_ATTR),
+            2 => reply.attr(&TTL, &self.file.lock().unwrap().attr),
+            _ => reply.error(ENOENT),
+        }
+    }
+
+    async fn setattr(
+        &self,
+        _req: &Request,
+        ino: u64,
+        mode: Option<u32>,
+        _uid: Option<u32>,
+        _gid: Option<u32>,
+        size: Option<u64>,
+        atime: Option<TimeOrNow>,
+        mtime: Option<TimeOrNow>,
+        ctime: Option<SystemTime>,
+        _fh: Option<u64>,
+        _crtime: Option<SystemTime>,
+        _chgtime: Option<SystemTime>,
+        _bkuptime: Option<SystemTime>,
+        _flags: Option<u32>,
+        reply: ReplyAttr,
+    ) {
+        if ino != 2 {
+            reply.error(ENOENT);
+            return;
+        }
+        let mut file = self.file.lock().unwrap();
+        if let Some(mode) = mode {
+            file.attr.perm = (mode & 0o7777) as u16;
+        }
+        // The kernel decides about size and times, store them as given
+        if let Some(size) = size {
+            file.data.resize(size as usize, 0);
+            file.attr.size = size;
+        }
+        let time = |time| match time {
+            TimeOrNow::SpecificTime(time) => time,
+            TimeOrNow::Now => SystemTime::now(),
+        };
+        if let Some(atime) = atime {
+            file.attr.atime = time(atime);
+        }
+        if let Some(mtime) = mtime {
+            file.attr.mtime = time(mtime);
+        }
+        if let Some(ctime) = ctime {
+            file.attr.ctime = ctime;
+        }
+        reply.attr(&TTL, &file.attr);
+    }
+
+    async fn open(&self, _req: &Request, ino: u64, _flags: u32, reply: ReplyOpen) {
+        if ino != 2 {
+            reply.error(ENOENT);
+            return;
+        }
+        // The kernel may read from files opened write-only to fill the page cache, so a
+        // handle backed by a real file would need to be opened for reading as well
+        reply.opened(0, OpenOptions::empty());
+    }
+
+    async fn read(
+        &self,
+        _req: &Request,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        size: u32,
+        reply: ReplyData,
+    ) {
+        if ino != 2 {
+            reply.error(ENOENT);
+            return;
+        }
+        let file = self.file.lock().unwrap();
+        let start = (offset as usize).min(file.data.len());
+        let end = (start + size as usize).min(file.data.len());
+        reply.data(&file.data[start..end]);
+    }
+
+    async fn write(
+        &self,
+        _req: &Request,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        data: &[u8],
+        flags: u32,
+        reply: ReplyWrite,
+    ) {
+        if ino != 2 {
+            reply.error(ENOENT);
+            return;
+        }
+        // Delayed writes from the page cache carry a guessed file handle, which is why the
+        // file handle isn't used at all
+        if flags & FUSE_WRITE_CACHE != 0 {
+            debug!("Delayed write of {} bytes at {}", data.len(), offset);
+        }
+        let mut file = self.file.lock().unwrap();
+        let end = offset as usize + data.len();
+        if file.data.len() < end {
+            file.data.resize(end, 0);
+        }
+        file.data[offset as usize..end].copy_from_slice(data);
+        // Without writeback cache, the size and times would be updated here. In writeback
+        // cache mode, the kernel sends them with setattr, but growing the size keeps reads
+        // consistent until then.
+        file.attr.size = file.attr.size.max(end as u64);
+        reply.written(data.len() as u32);
+    }
+
+    async fn readdir(
+        &self,
+        _req: &Request,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        mut reply: ReplyDirectory,
+    ) {
+        if ino != 1 {
+            reply.error(ENOENT);
+            return;
+        }
+
+        let entries = vec![
+            (1, FileType::Directory, "."),
+            (1, FileType::Directory, ".."),
+            (2, FileType::RegularFile, "log.txt"),
+        ];
+
+        for (i, entry) in entries.into_iter().enumerate().skip(offset as usize) {
+            // i + 1 means the index of the next entry
+            reply.add(entry.0, (i + 1) as i64, entry.1, entry.2);
+        }
+        reply.ok();
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    env_logger::init();
+    let mountpoint = env::args_os().nth(1).unwrap();
+    let options = ["-o", "fsname=writeback"]
+        .iter()
+        .map(|s| OsString::from(&s))
+        .collect::<Vec<OsString>>();
+    async_fuse::mount(WritebackFS::new(), mountpoint, &options)
+        .await
+        .unwrap();
+}
@@ -16,42 +16,36 @@
 //! - supports ABI 7.19 since FUSE 2.9.1
 //! - supports ABI 7.26 since FUSE 3.0.0
 //!
-//! Items without a version annotation are valid with ABI 7.8 and later
+//! All definitions follow the latest supported ABI version. The protocol version is
+//! negotiated at runtime and kernels with older ABI versions send and expect shorter variants
+//! of some structs (see the `FUSE_COMPAT_*` sizes).
 
 #![warn(missing_debug_implementations, rust_2018_idioms)]
 #![allow(missing_docs)]
 
 use std::convert::TryFrom;
+use std::mem;
 
 pub const FUSE_KERNEL_VERSION: u32 = 7;
 
-#[cfg(not(feature = "abi-7-9"))]
-pub const FUSE_KERNEL_MINOR_VERSION: u32 = 8;
-#[cfg(all(feature = "abi-7-9", not(feature = "abi-7-10")))]
-pub const FUSE_KERNEL_MINOR_VERSION: u32 = 9;
-#[cfg(all(feature = "abi-7-10", not(feature = "abi-7-11")))]
-pub const FUSE_KERNEL_MINOR_VERSION: u32 = 10;
-#[cfg(all(feature = "abi-7-11", not(feature = "abi-7-12")))]
-pub const FUSE_KERNEL_MINOR_VERSION: u32 = 11;
-#[cfg(all(feature = "abi-7-12", not(feature = "abi-7-13")))]
-pub const FUSE_KERNEL_MINOR_VERSION: u32 = 12;
-#[cfg(all(feature = "abi-7-13", not(feature = "abi-7-14")))]
-pub const FUSE_KERNEL_MINOR_VERSION: u32 = 13;
-#[cfg(all(feature = "abi-7-14", not(feature = "abi-7-15")))]
-pub const FUSE_KERNEL_MINOR_VERSION: u32 = 14;
-#[cfg(all(feature = "abi-7-15", not(feature = "abi-7-16")))]
-pub const FUSE_KERNEL_MINOR_VERSION: u32 = 15;
-#[cfg(all(feature = "abi-7-16", not(feature = "abi-7-17")))]
-pub const FUSE_KERNEL_MINOR_VERSION: u32 = 16;
-#[cfg(all(feature = "abi-7-17", not(feature = "abi-7-18")))]
-pub const FUSE_KERNEL_MINOR_VERSION: u32 = 17;
-#[cfg(all(feature = "abi-7-18", not(feature = "abi-7-19")))]
-pub const FUSE_KERNEL_MINOR_VERSION: u32 = 18;
-#[cfg(feature = "abi-7-19")]
-pub const FUSE_KERNEL_MINOR_VERSION: u32 = 19;
+pub const FUSE_KERNEL_MINOR_VERSION: u32 = 40;
 
 pub const FUSE_ROOT_ID: u64 = 1;
 
+// Sizes of structs sent and expected by kernels with older ABI versions. Before ABI 7.9,
+// fuse_attr ended without the blksize and padding fields.
+pub const FUSE_COMPAT_ATTR_OUT_SIZE: usize = mem::size_of::<fuse_attr_out>() - 8;
+pub const FUSE_COMPAT_ENTRY_OUT_SIZE: usize = mem::size_of::<fuse_entry_out>() - 8;
+pub const FUSE_COMPAT_READ_IN_SIZE: usize = 24; // before ABI 7.9
+pub const FUSE_COMPAT_WRITE_IN_SIZE: usize = 24; // before ABI 7.9
+pub const FUSE_COMPAT_LK_IN_SIZE: usize = 40; // before ABI 7.9
+pub const FUSE_COMPAT_MKNOD_IN_SIZE: usize = 8; // before ABI 7.12
+pub const FUSE_COMPAT_CREATE_IN_SIZE: usize = 8; // before ABI 7.12
+pub const FUSE_COMPAT_22_INIT_OUT_SIZE: usize = 24; // before ABI 7.23
+pub const FUSE_COMPAT_INIT_IN_SIZE: usize = 16; // before ABI 7.36
+#[cfg(not(target_os = "macos"))]
+pub const FUSE_COMPAT_SETXATTR_IN_SIZE: usize = 8; // without FUSE_SETXATTR_EXT
+
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
 pub struct fuse_attr {
@@ -75,9 +69,7 @@ pub struct fuse_attr {
     pub rdev: u32,
     #[cfg(target_os = "macos")]
     pub flags: u32, // see chflags(2)
-    #[cfg(feature = "abi-7-9")]
     pub blksize: u32,
-    #[cfg(feature = "abi-7-9")]
     pub padding: u32,
 }
 
@@ -114,12 +106,10 @@ pub mod consts {
     pub const FATTR_ATIME: u32 = 1 << 4;
     pub const FATTR_MTIME: u32 = 1 << 5;
     pub const FATTR_FH: u32 = 1 << 6;
-    #[cfg(feature = "abi-7-9")]
     pub const FATTR_ATIME_NOW: u32 = 1 << 7;
-    #[cfg(feature = "abi-7-9")]
     pub const FATTR_MTIME_NOW: u32 = 1 << 8;
-    #[cfg(feature = "abi-7-9")]
     pub const FATTR_LOCKOWNER: u32 = 1 << 9;
+    pub const FATTR_CTIME: u32 = 1 << 10;
 
     #[cfg(target_os = "macos")]
     pub const FATTR_CRTIME: u32 = 1 << 28;
@@ -133,8 +123,12 @@ pub mod consts {
     // Flags returned by the open request
     pub const FOPEN_DIRECT_IO: u32 = 1 << 0; // bypass page cache for this open file
     pub const FOPEN_KEEP_CACHE: u32 = 1 << 1; // don't invalidate the data cache on open
-    #[cfg(feature = "abi-7-10")]
     pub const FOPEN_NONSEEKABLE: u32 = 1 << 2; // the file is not seekable
+    pub const FOPEN_CACHE_DIR: u32 = 1 << 3; // allow caching this directory
+    pub const FOPEN_STREAM: u32 = 1 << 4; // the file is stream-like (no file position at all)
+    pub const FOPEN_NOFLUSH: u32 = 1 << 5; // don't flush data cache on close (unless FUSE_WRITEBACK_CACHE)
+    pub const FOPEN_PARALLEL_DIRECT_WRITES: u32 = 1 << 6; // allow concurrent direct writes on the same inode
+    pub const FOPEN_PASSTHROUGH: u32 = 1 << 7; // passthrough read/write io for this open file
 
     #[cfg(target_os = "macos")]
     pub const FOPEN_PURGE_ATTR: u32 = 1 << 30;
@@ -142,89 +136,115 @@ pub mod consts {
     pub const FOPEN_PURGE_UBC: u32 = 1 << 31;
 
     // Init request/reply flags
-    pub const FUSE_ASYNC_READ: u32 = 1 << 0; // asynchronous read requests
-    pub const FUSE_POSIX_LOCKS: u32 = 1 << 1; // remote locking for POSIX file locks
-    #[cfg(feature = "abi-7-9")]
-    pub const FUSE_FILE_OPS: u32 = 1 << 2; // kernel sends file handle for fstat, etc...
-    #[cfg(feature = "abi-7-9")]
-    pub const FUSE_ATOMIC_O_TRUNC: u32 = 1 << 3; // handles the O_TRUNC open flag in the filesystem
-    #[cfg(feature = "abi-7-10")]
-    pub const FUSE_EXPORT_SUPPORT: u32 = 1 << 4; // filesystem handles lookups of "." and ".."
-    #[cfg(feature = "abi-7-9")]
-    pub const FUSE_BIG_WRITES: u32 = 1 << 5; // filesystem can handle write size larger than 4kB
-    #[cfg(feature = "abi-7-12")]
-    pub const FUSE_DONT_MASK: u32 = 1 << 6; // don't apply umask to file mode on create operations
-
-    #[cfg(all(feature = "abi-7-14", not(target_os = "macos")))]
-    pub const FUSE_SPLICE_WRITE: u32 = 1 << 7; // kernel supports splice write on the device
-    #[cfg(all(feature = "abi-7-14", not(target_os = "macos")))]
-    pub const FUSE_SPLICE_MOVE: u32 = 1 << 8; // kernel supports splice move on the device
+    pub const FUSE_ASYNC_READ: u64 = 1 << 0; // asynchronous read requests
+    pub const FUSE_POSIX_LOCKS: u64 = 1 << 1; // remote locking for POSIX file locks
+    pub const FUSE_FILE_OPS: u64 = 1 << 2; // kernel sends file handle for fstat, etc...
+    pub const FUSE_ATOMIC_O_TRUNC: u64 = 1 << 3; // handles the O_TRUNC open flag in the filesystem
+    pub const FUSE_EXPORT_SUPPORT: u64 = 1 << 4; // filesystem handles lookups of "." and ".."
+    pub const FUSE_BIG_WRITES: u64 = 1 << 5; // filesystem can handle write size larger than 4kB
+    pub const FUSE_DONT_MASK: u64 = 1 << 6; // don't apply umask to file mode on create operations
+
+    #[cfg(not(target_os = "macos"))]
+    pub const FUSE_SPLICE_WRITE: u64 = 1 << 7; // kernel supports splice write on the device
+    #[cfg(not(target_os = "macos"))]
+    pub const FUSE_SPLICE_MOVE: u64 = 1 << 8; // kernel supports splice move on the device
+    #[cfg(not(target_os = "macos"))]
+    pub const FUSE_SPLICE_READ: u64 = 1 << 9; // kernel supports splice read on the device
+    pub const FUSE_FLOCK_LOCKS: u64 = 1 << 10; // remote locking for BSD style file locks
+    pub const FUSE_HAS_IOCTL_DIR: u64 = 1 << 11; // kernel supports ioctl on directories
+    pub const FUSE_AUTO_INVAL_DATA: u64 = 1 << 12; // automatically invalidate cached pages
+    pub const FUSE_DO_READDIRPLUS: u64 = 1 << 13; // do READDIRPLUS (READDIR+LOOKUP in one)
+    pub const FUSE_READDIRPLUS_AUTO: u64 = 1 << 14; // adaptive readdirplus
+    pub const FUSE_ASYNC_DIO: u64 = 1 << 15; // asynchronous direct I/O submission
+    pub const FUSE_WRITEBACK_CACHE: u64 = 1 << 16; // use writeback cache for buffered writes
+    pub const FUSE_NO_OPEN_SUPPORT: u64 = 1 << 17; // kernel supports zero-message opens
+    pub const FUSE_PARALLEL_DIROPS: u64 = 1 << 18; // allow parallel lookups and readdir
+    pub const FUSE_HANDLE_KILLPRIV: u64 = 1 << 19; // fs handles killing suid/sgid/cap on write/chown/trunc
+    pub const FUSE_POSIX_ACL: u64 = 1 << 20; // filesystem supports posix acls
+    pub const FUSE_ABORT_ERROR: u64 = 1 << 21; // reading the device after abort returns ECONNABORTED
+    pub const FUSE_MAX_PAGES: u64 = 1 << 22; // init_out.max_pages contains the max number of req pages
+    pub const FUSE_CACHE_SYMLINKS: u64 = 1 << 23; // cache READLINK responses
+    pub const FUSE_NO_OPENDIR_SUPPORT: u64 = 1 << 24; // kernel supports zero-message opendir
+    pub const FUSE_EXPLICIT_INVAL_DATA: u64 = 1 << 25; // only invalidate cached pages on explicit request
+    pub const FUSE_MAP_ALIGNMENT: u64 = 1 << 26; // init_out.map_alignment contains log2(byte alignment)
+    #[cfg(not(target_os = "macos"))]
+    pub const FUSE_SUBMOUNTS: u64 = 1 << 27; // kernel supports auto-mounting directory submounts
+    #[cfg(not(target_os = "macos"))]
+    pub const FUSE_HANDLE_KILLPRIV_V2: u64 = 1 << 28; // fs kills suid/sgid/cap on write/chown/trunc
     #[cfg(not(target_os = "macos"))]
-    #[cfg(feature = "abi-7-14")]
-    pub const FUSE_SPLICE_READ: u32 = 1 << 9; // kernel supports splice read on the device
-    #[cfg(feature = "abi-7-17")]
-    pub const FUSE_FLOCK_LOCKS: u32 = 1 << 10; // remote locking for BSD style file locks
-    #[cfg(feature = "abi-7-18")]
-    pub const FUSE_HAS_IOCTL_DIR: u32 = 1 << 11; // kernel supports ioctl on directories
+    pub const FUSE_SETXATTR_EXT: u64 = 1 << 29; // server supports extended struct fuse_setxattr_in
+    #[cfg(not(target_os = "macos"))]
+    pub const FUSE_INIT_EXT: u64 = 1 << 30; // extended fuse_init_in request (flags2)
+    #[cfg(not(target_os = "macos"))]
+    pub const FUSE_INIT_RESERVED: u64 = 1 << 31; // reserved, do not use
+    pub const FUSE_SECURITY_CTX: u64 = 1 << 32; // add security context to create, mkdir, symlink, and mknod
+    pub const FUSE_HAS_INODE_DAX: u64 = 1 << 33; // use per inode DAX
+    pub const FUSE_CREATE_SUPP_GROUP: u64 = 1 << 34; // add supplementary group info to create, mkdir, symlink and mknod
+    pub const FUSE_HAS_EXPIRE_ONLY: u64 = 1 << 35; // kernel supports expiry-only entry invalidation
+    pub const FUSE_DIRECT_IO_ALLOW_MMAP: u64 = 1 << 36; // allow shared mmap in FOPEN_DIRECT_IO mode
+    pub const FUSE_PASSTHROUGH: u64 = 1 << 37; // passthrough mode for read/write io
+    pub const FUSE_NO_EXPORT_SUPPORT: u64 = 1 << 38; // explicitly disable export support
+    pub const FUSE_HAS_RESEND: u64 = 1 << 39; // kernel supports resending pending requests
 
     #[cfg(target_os = "macos")]
-    pub const FUSE_ALLOCATE: u32 = 1 << 27;
+    pub const FUSE_ALLOCATE: u64 = 1 << 27;
     #[cfg(target_os = "macos")]
-    pub const FUSE_EXCHANGE_DATA: u32 = 1 << 28;
+    pub const FUSE_EXCHANGE_DATA: u64 = 1 << 28;
     #[cfg(target_os = "macos")]
-    pub const FUSE_CASE_INSENSITIVE: u32 = 1 << 29;
+    pub const FUSE_CASE_INSENSITIVE: u64 = 1 << 29;
     #[cfg(target_os = "macos")]
-    pub const FUSE_VOL_RENAME: u32 = 1 << 30;
+    pub const FUSE_VOL_RENAME: u64 = 1 << 30;
     #[cfg(target_os = "macos")]
-    pub const FUSE_XTIMES: u32 = 1 << 31;
+    pub const FUSE_XTIMES: u64 = 1 << 31;
 
     // CUSE init request/reply flags
-    #[cfg(feature = "abi-7-12")]
     pub const CUSE_UNRESTRICTED_IOCTL: u32 = 1 << 0; // use unrestricted ioctl
 
     // Release flags
     pub const FUSE_RELEASE_FLUSH: u32 = 1 << 0;
-    #[cfg(feature = "abi-7-17")]
     pub const FUSE_RELEASE_FLOCK_UNLOCK: u32 = 1 << 1;
 
     // Getattr flags
-    #[cfg(feature = "abi-7-9")]
     pub const FUSE_GETATTR_FH: u32 = 1 << 0;
 
+    // Setxattr flags (FUSE_SETXATTR_EXT)
+    #[cfg(not(target_os = "macos"))]
+    pub const FUSE_SETXATTR_ACL_KILL_SGID: u32 = 1 << 0; // clear SGID when system.posix_acl_access is set
+
     // Lock flags
-    #[cfg(feature = "abi-7-9")]
     pub const FUSE_LK_FLOCK: u32 = 1 << 0;
 
     // Write flags
-    #[cfg(feature = "abi-7-9")]
     pub const FUSE_WRITE_CACHE: u32 = 1 << 0; // delayed write from page cache, file handle is guessed
-    #[cfg(feature = "abi-7-9")]
     pub const FUSE_WRITE_LOCKOWNER: u32 = 1 << 1; // lock_owner field is valid
 
     // Read flags
-    #[cfg(feature = "abi-7-9")]
     pub const FUSE_READ_LOCKOWNER: u32 = 1 << 1;
 
     // IOCTL flags
-    #[cfg(feature = "abi-7-11")]
     pub const FUSE_IOCTL_COMPAT: u32 = 1 << 0; // 32bit compat ioctl on 64bit machine
-    #[cfg(feature = "abi-7-11")]
     pub const FUSE_IOCTL_UNRESTRICTED: u32 = 1 << 1; // not restricted to well-formed ioctls, retry allowed
-    #[cfg(feature = "abi-7-11")]
     pub const FUSE_IOCTL_RETRY: u32 = 1 << 2; // retry with new iovecs
-    #[cfg(feature = "abi-7-16")]
     pub const FUSE_IOCTL_32BIT: u32 = 1 << 3; // 32bit ioctl
-    #[cfg(feature = "abi-7-18")]
     pub const FUSE_IOCTL_DIR: u32 = 1 << 4; // is a directory
-    #[cfg(feature = "abi-7-11")]
     pub const FUSE_IOCTL_MAX_IOV: u32 = 256; // maximum of in_iovecs + out_iovecs
 
     // Poll flags
-    #[cfg(feature = "abi-7-9")]
     pub const FUSE_POLL_SCHEDULE_NOTIFY: u32 = 1 << 0; // request poll notify
 
+    // Request extensions (ABI 7.36 and later)
+    pub const FUSE_MAX_NR_SECCTX: u32 = 31; // extension types up to this are security context headers
+
     // The read buffer is required to be at least 8k, but may be much larger
     pub const FUSE_MIN_READ_BUFFER: usize = 8192;
+
+    // Device ioctls (Linux 4.5 and later)
+    #[cfg(target_os = "linux")]
+    pub const FUSE_DEV_IOC_CLONE: u32 = 0x8004_e500; // _IOR(229, 0, uint32_t), clone a /dev/fuse fd
+    #[cfg(target_os = "linux")]
+    pub const FUSE_DEV_IOC_BACKING_OPEN: u32 = 0x4010_e501; // _IOW(229, 1, struct fuse_backing_map), register a backing file
+    #[cfg(target_os = "linux")]
+    pub const FUSE_DEV_IOC_BACKING_CLOSE: u32 = 0x4004_e502; // _IOW(229, 2, uint32_t), unregister a backing file
 }
 
 /// Invalid opcode error.
@@ -271,16 +291,18 @@ pub enum fuse_opcode {
     FUSE_INTERRUPT = 36,
     FUSE_BMAP = 37,
     FUSE_DESTROY = 38,
-    #[cfg(feature = "abi-7-11")]
     FUSE_IOCTL = 39,
-    #[cfg(feature = "abi-7-11")]
     FUSE_POLL = 40,
-    #[cfg(feature = "abi-7-15")]
     FUSE_NOTIFY_REPLY = 41,
-    #[cfg(feature = "abi-7-16")]
     FUSE_BATCH_FORGET = 42,
-    #[cfg(feature = "abi-7-19")]
     FUSE_FALLOCATE = 43,
+    FUSE_READDIRPLUS = 44,
+    FUSE_RENAME2 = 45,
+    FUSE_LSEEK = 46,
+    FUSE_COPY_FILE_RANGE = 47,
+    FUSE_SYNCFS = 50,
+    FUSE_TMPFILE = 51,
+    FUSE_STATX = 52,
 
     #[cfg(target_os = "macos")]
     FUSE_SETVOLNAME = 61,
@@ -289,7 +311,6 @@ pub enum fuse_opcode {
     #[cfg(target_os = "macos")]
     FUSE_EXCHANGE = 63,
 
-    #[cfg(feature = "abi-7-12")]
     CUSE_INIT = 4096,
 }
 
@@ -334,16 +355,18 @@ impl TryFrom<u32> for fuse_opcode {
             36 => Ok(fuse_opcode::FUSE_INTERRUPT),
             37 => Ok(fuse_opcode::FUSE_BMAP),
             38 => Ok(fuse_opcode::FUSE_DESTROY),
-            #[cfg(feature = "abi-7-11")]
             39 => Ok(fuse_opcode::FUSE_IOCTL),
-            #[cfg(feature = "abi-7-11")]
             40 => Ok(fuse_opcode::FUSE_POLL),
-            #[cfg(feature = "abi-7-15")]
             41 => Ok(fuse_opcode::FUSE_NOTIFY_REPLY),
-            #[cfg(feature = "abi-7-16")]
             42 => Ok(fuse_opcode::FUSE_BATCH_FORGET),
-            #[cfg(feature = "abi-7-19")]
             43 => Ok(fuse_opcode::FUSE_FALLOCATE),
+            44 => Ok(fuse_opcode::FUSE_READDIRPLUS),
+            45 => Ok(fuse_opcode::FUSE_RENAME2),
+            46 => Ok(fuse_opcode::FUSE_LSEEK),
+            47 => Ok(fuse_opcode::FUSE_COPY_FILE_RANGE),
+            50 => Ok(fuse_opcode::FUSE_SYNCFS),
+            51 => Ok(fuse_opcode::FUSE_TMPFILE),
+            52 => Ok(fuse_opcode::FUSE_STATX),
 
             #[cfg(target_os = "macos")]
             61 => Ok(fuse_opcode::FUSE_SETVOLNAME),
@@ -352,7 +375,6 @@ impl TryFrom<u32> for fuse_opcode {
             #[cfg(target_os = "macos")]
             63 => Ok(fuse_opcode::FUSE_EXCHANGE),
 
-            #[cfg(feature = "abi-7-12")]
             4096 => Ok(fuse_opcode::CUSE_INIT),
 
             _ => Err(InvalidOpcodeError),
@@ -361,46 +383,31 @@ impl TryFrom<u32> for fuse_opcode {
 }
 
 /// Invalid notify code error.
-#[cfg(feature = "abi-7-11")]
 #[derive(Debug, Clone, Copy)]
 pub struct InvalidNotifyCodeError;
 
-#[cfg(feature = "abi-7-11")]
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
 #[allow(non_camel_case_types)]
 pub enum fuse_notify_code {
-    #[cfg(feature = "abi-7-11")]
     FUSE_POLL = 1,
-    #[cfg(feature = "abi-7-12")]
     FUSE_NOTIFY_INVAL_INODE = 2,
-    #[cfg(feature = "abi-7-12")]
     FUSE_NOTIFY_INVAL_ENTRY = 3,
-    #[cfg(feature = "abi-7-15")]
     FUSE_NOTIFY_STORE = 4,
-    #[cfg(feature = "abi-7-15")]
     FUSE_NOTIFY_RETRIEVE = 5,
-    #[cfg(feature = "abi-7-18")]
     FUSE_NOTIFY_DELETE = 6,
 }
 
-#[cfg(feature = "abi-7-11")]
 impl TryFrom<u32> for fuse_notify_code {
     type Error = InvalidNotifyCodeError;
 
     fn try_from(n: u32) -> Result<Self, Self::Error> {
         match n {
-            #[cfg(feature = "abi-7-11")]
             1 => Ok(fuse_notify_code::FUSE_POLL),
-            #[cfg(feature = "abi-7-12")]
             2 => Ok(fuse_notify_code::FUSE_NOTIFY_INVAL_INODE),
-            #[cfg(feature = "abi-7-12")]
             3 => Ok(fuse_notify_code::FUSE_NOTIFY_INVAL_ENTRY),
-            #[cfg(feature = "abi-7-15")]
             4 => Ok(fuse_notify_code::FUSE_NOTIFY_STORE),
-            #[cfg(feature = "abi-7-15")]
             5 => Ok(fuse_notify_code::FUSE_NOTIFY_RETRIEVE),
-            #[cfg(feature = "abi-7-18")]
             6 => Ok(fuse_notify_code::FUSE_NOTIFY_DELETE),
 
             _ => Err(InvalidNotifyCodeError),
@@ -426,7 +433,6 @@ pub struct fuse_forget_in {
     pub nlookup: u64,
 }
 
-#[cfg(feature = "abi-7-16")]
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
 pub struct fuse_forget_one {
@@ -434,7 +440,6 @@ pub struct fuse_forget_one {
     pub nlookup: u64,
 }
 
-#[cfg(feature = "abi-7-16")]
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
 pub struct fuse_batch_forget_in {
@@ -442,7 +447,6 @@ pub struct fuse_batch_forget_in {
     pub dummy: u32,
 }
 
-#[cfg(feature = "abi-7-9")]
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
 pub struct fuse_getattr_in {
@@ -475,9 +479,7 @@ pub struct fuse_getxtimes_out {
 pub struct fuse_mknod_in {
     pub mode: u32,
     pub rdev: u32,
-    #[cfg(feature = "abi-7-12")]
     pub umask: u32,
-    #[cfg(feature = "abi-7-12")]
     pub padding: u32,
 }
 
@@ -485,9 +487,6 @@ pub struct fuse_mknod_in {
 #[derive(Debug, Clone, Copy)]
 pub struct fuse_mkdir_in {
     pub mode: u32,
-    #[cfg(not(feature = "abi-7-12"))]
-    pub padding: u32,
-    #[cfg(feature = "abi-7-12")]
     pub umask: u32,
 }
 
@@ -497,6 +496,14 @@ pub struct fuse_rename_in {
     pub newdir: u64,
 }
 
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct fuse_rename2_in {
+    pub newdir: u64,
+    pub flags: u32,
+    pub padding: u32,
+}
+
 #[cfg(target_os = "macos")]
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
@@ -519,16 +526,13 @@ pub struct fuse_setattr_in {
     pub padding: u32,
     pub fh: u64,
     pub size: u64,
-    #[cfg(not(feature = "abi-7-9"))]
-    pub unused1: u64,
-    #[cfg(feature = "abi-7-9")]
     pub lock_owner: u64,
     pub atime: u64,
     pub mtime: u64,
-    pub unused2: u64,
+    pub ctime: u64,
     pub atimensec: u32,
     pub mtimensec: u32,
-    pub unused3: u32,
+    pub ctimensec: u32,
     pub mode: u32,
     pub unused4: u32,
     pub uid: u32,
@@ -562,9 +566,7 @@ pub struct fuse_open_in {
 pub struct fuse_create_in {
     pub flags: u32,
     pub mode: u32,
-    #[cfg(feature = "abi-7-12")]
     pub umask: u32,
-    #[cfg(feature = "abi-7-12")]
     pub padding: u32,
 }
 
@@ -573,7 +575,7 @@ pub struct fuse_create_in {
 pub struct fuse_open_out {
     pub fh: u64,
     pub open_flags: u32,
-    pub padding: u32,
+    pub backing_id: i32,
 }
 
 #[repr(C)]
@@ -600,13 +602,9 @@ pub struct fuse_read_in {
     pub fh: u64,
     pub offset: u64,
     pub size: u32,
-    #[cfg(feature = "abi-7-9")]
     pub read_flags: u32,
-    #[cfg(feature = "abi-7-9")]
     pub lock_owner: u64,
-    #[cfg(feature = "abi-7-9")]
     pub flags: u32,
-    #[cfg(feature = "abi-7-9")]
     pub padding: u32,
 }
 
@@ -617,11 +615,8 @@ pub struct fuse_write_in {
     pub offset: u64,
     pub size: u32,
     pub write_flags: u32,
-    #[cfg(feature = "abi-7-9")]
     pub lock_owner: u64,
-    #[cfg(feature = "abi-7-9")]
     pub flags: u32,
-    #[cfg(feature = "abi-7-9")]
     pub padding: u32,
 }
 
@@ -651,9 +646,10 @@ pub struct fuse_fsync_in {
 pub struct fuse_setxattr_in {
     pub size: u32,
     pub flags: u32,
+    #[cfg(not(target_os = "macos"))]
+    pub setxattr_flags: u32,
     #[cfg(target_os = "macos")]
     pub position: u32,
-    #[cfg(target_os = "macos")]
     pub padding: u32,
 }
 
@@ -681,9 +677,7 @@ pub struct fuse_lk_in {
     pub fh: u64,
     pub owner: u64,
     pub lk: fuse_file_lock,
-    #[cfg(feature = "abi-7-9")]
     pub lk_flags: u32,
-    #[cfg(feature = "abi-7-9")]
     pub padding: u32,
 }
 
@@ -707,6 +701,8 @@ pub struct fuse_init_in {
     pub minor: u32,
     pub max_readahead: u32,
     pub flags: u32,
+    pub flags2: u32,
+    pub unused: [u32; 11],
 }
 
 #[repr(C)]
@@ -716,16 +712,17 @@ pub struct fuse_init_out {
     pub minor: u32,
     pub max_readahead: u32,
     pub flags: u32,
-    #[cfg(not(feature = "abi-7-13"))]
-    pub unused: u32,
-    #[cfg(feature = "abi-7-13")]
     pub max_background: u16,
-    #[cfg(feature = "abi-7-13")]
     pub congestion_threshold: u16,
     pub max_write: u32,
+    pub time_gran: u32,
+    pub max_pages: u16,
+    pub map_alignment: u16,
+    pub flags2: u32,
+    pub max_stack_depth: u32,
+    pub unused: [u32; 6],
 }
 
-#[cfg(feature = "abi-7-12")]
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
 pub struct cuse_init_in {
@@ -735,7 +732,6 @@ pub struct cuse_init_in {
     pub flags: u32,
 }
 
-#[cfg(feature = "abi-7-12")]
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
 pub struct cuse_init_out {
@@ -770,7 +766,6 @@ pub struct fuse_bmap_out {
     pub block: u64,
 }
 
-#[cfg(feature = "abi-7-11")]
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
 pub struct fuse_ioctl_in {
@@ -782,7 +777,6 @@ pub struct fuse_ioctl_in {
     pub out_size: u32,
 }
 
-#[cfg(feature = "abi-7-16")]
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
 pub struct fuse_ioctl_iovec {
@@ -790,7 +784,6 @@ pub struct fuse_ioctl_iovec {
     pub len: u64,
 }
 
-#[cfg(feature = "abi-7-11")]
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
 pub struct fuse_ioctl_out {
@@ -800,7 +793,6 @@ pub struct fuse_ioctl_out {
     pub out_iovs: u32,
 }
 
-#[cfg(feature = "abi-7-11")]
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
 pub struct fuse_poll_in {
@@ -810,7 +802,6 @@ pub struct fuse_poll_in {
     pub padding: u32,
 }
 
-#[cfg(feature = "abi-7-11")]
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
 pub struct fuse_poll_out {
@@ -818,22 +809,55 @@ pub struct fuse_poll_out {
     pub padding: u32,
 }
 
-#[cfg(feature = "abi-7-11")]
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
 pub struct fuse_notify_poll_wakeup_out {
     pub kh: u64,
 }
 
-#[cfg(feature = "abi-7-19")]
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
 pub struct fuse_fallocate_in {
-    fh: u64,
-    offset: u64,
-    length: u64,
-    mode: u32,
-    padding: u32,
+    pub fh: u64,
+    pub offset: u64,
+    pub length: u64,
+    pub mode: u32,
+    pub padding: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct fuse_lseek_in {
+    pub fh: u64,
+    pub offset: u64,
+    pub whence: u32,
+    pub padding: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct fuse_lseek_out {
+    pub offset: u64,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct fuse_backing_map {
+    pub fd: i32,
+    pub flags: u32,
+    pub padding: u64,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct fuse_copy_file_range_in {
+    pub fh_in: u64,
+    pub off_in: u64,
+    pub nodeid_out: u64,
+    pub fh_out: u64,
+    pub off_out: u64,
+    pub len: u64,
+    pub flags: u64,
 }
 
 #[repr(C)]
@@ -867,7 +891,13 @@ pub struct fuse_dirent {
     // followed by name of namelen bytes
 }
 
-#[cfg(feature = "abi-7-12")]
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct fuse_direntplus {
+    pub entry_out: fuse_entry_out,
+    pub dirent: fuse_dirent,
+}
+
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
 pub struct fuse_notify_inval_inode_out {
@@ -876,7 +906,6 @@ pub struct fuse_notify_inval_inode_out {
     pub len: i64,
 }
 
-#[cfg(feature = "abi-7-12")]
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
 pub struct fuse_notify_inval_entry_out {
@@ -885,17 +914,15 @@ pub struct fuse_notify_inval_entry_out {
     pub padding: u32,
 }
 
-#[cfg(feature = "abi-7-18")]
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
 pub struct fuse_notify_delete_out {
-    parent: u64,
-    child: u64,
-    namelen: u32,
-    padding: u32,
+    pub parent: u64,
+    pub child: u64,
+    pub namelen: u32,
+    pub padding: u32,
 }
 
-#[cfg(feature = "abi-7-15")]
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
 pub struct fuse_notify_store_out {
@@ -905,7 +932,6 @@ pub struct fuse_notify_store_out {
     pub padding: u32,
 }
 
-#[cfg(feature = "abi-7-15")]
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
 pub struct fuse_notify_retrieve_out {
@@ -916,7 +942,6 @@ pub struct fuse_notify_retrieve_out {
     pub padding: u32,
 }
 
-#[cfg(feature = "abi-7-15")]
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
 pub struct fuse_notify_retrieve_in {
@@ -928,3 +953,77 @@ pub struct fuse_notify_retrieve_in {
     pub dummy3: u64,
     pub dummy4: u64,
 }
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct fuse_syncfs_in {
+    pub padding: u64,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct fuse_sx_time {
+    pub tv_sec: i64,
+    pub tv_nsec: u32,
+    pub reserved: i32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct fuse_statx {
+    pub mask: u32,
+    pub blksize: u32,
+    pub attributes: u64,
+    pub nlink: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub mode: u16,
+    pub spare0: [u16; 1],
+    pub ino: u64,
+    pub size: u64,
+    pub blocks: u64,
+    pub attributes_mask: u64,
+    pub atime: fuse_sx_time,
+    pub btime: fuse_sx_time,
+    pub ctime: fuse_sx_time,
+    pub mtime: fuse_sx_time,
+    pub rdev_major: u32,
+    pub rdev_minor: u32,
+    pub dev_major: u32,
+    pub dev_minor: u32,
+    pub spare2: [u64; 14],
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct fuse_statx_in {
+    pub getattr_flags: u32,
+    pub reserved: u32,
+    pub fh: u64,
+    pub sx_flags: u32,
+    pub sx_mask: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct fuse_statx_out {
+    pub attr_valid: u64,
+    pub attr_valid_nsec: u32,
+    pub flags: u32,
+    pub spare: [u64; 2],
+    pub stat: fuse_statx,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct fuse_secctx {
+    pub size: u32,
+    pub padding: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct fuse_secctx_header {
+    pub size: u32,
+    pub nr_secctx: u32,
+}
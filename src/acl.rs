@@ -0,0 +1,226 @@
+//! POSIX access control lists
+//!
+//! With the `FUSE_POSIX_ACL` capability, the kernel checks permissions using POSIX ACLs. The
+//! filesystem stores them as extended attributes: the ACL of a file in
+//! `system.posix_acl_access` and the default ACL that new entries of a directory inherit in
+//! `system.posix_acl_default`. The kernel reads and writes them with getxattr and setxattr in
+//! a binary format, which is converted by `PosixAcl`.
+
+use libc::{c_int, EINVAL};
+use std::convert::TryInto;
+
+/// Name of the extended attribute holding the access ACL of a file
+pub const XATTR_POSIX_ACL_ACCESS: &str = "system.posix_acl_access";
+
+/// Name of the extended attribute holding the default ACL of a directory
+pub const XATTR_POSIX_ACL_DEFAULT: &str = "system.posix_acl_default";
+
+/// Version of the binary ACL format
+const POSIX_ACL_XATTR_VERSION: u32 = 0x0002;
+
+/// Size of an entry in the binary ACL format (tag, permissions and id)
+const ENTRY_SIZE: usize = 8;
+
+/// Id of entries that don't refer to a user or group
+const ACL_UNDEFINED_ID: u32 = u32::MAX;
+
+/// Tag of an ACL entry, telling whom it applies to
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum AclTag {
+    /// Owner of the file (ACL_USER_OBJ)
+    UserObj,
+    /// User with the given uid (ACL_USER)
+    User(u32),
+    /// Group of the file (ACL_GROUP_OBJ)
+    GroupObj,
+    /// Group with the given gid (ACL_GROUP)
+    Group(u32),
+    /// Maximum permissions of all group class entries (ACL_MASK)
+    Mask,
+    /// Everyone else (ACL_OTHER)
+    Other,
+}
+
+/// An ACL entry, granting the permissions (a combination of 4 = read, 2 = write and
+/// 1 = execute) to the users given by the tag
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct AclEntry {
+    /// Whom the entry applies to
+    pub tag: AclTag,
+    /// Permissions granted
+    pub perm: u16,
+}
+
+/// A POSIX ACL, as stored in the `system.posix_acl_access` and `system.posix_acl_default`
+/// extended attributes. Entries aren't checked for consistency, the kernel does that before
+/// setting an ACL.
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub struct PosixAcl {
+    /// Entries of the ACL
+    pub entries: Vec<AclEntry>,
+}
+
+impl PosixAcl {
+    /// Create the minimal ACL that is equivalent to the permission bits of the given mode
+    pub fn from_mode(mode: u32) -> PosixAcl {
+        let entry = |tag, shift: u32| AclEntry {
+            tag,
+            perm: (mode >> shift & 0o7) as u16,
+        };
+        PosixAcl {
+            entries: vec![
+                entry(AclTag::UserObj, 6),
+                entry(AclTag::GroupObj, 3),
+                entry(AclTag::Other, 0),
+            ],
+        }
+    }
+
+    /// Decode an ACL from the value of an ACL extended attribute. Returns EINVAL if the value
+    /// isn't a valid ACL.
+    pub fn from_xattr(value: &[u8]) -> Result<PosixAcl, c_int> {
+        if value.len() % ENTRY_SIZE != 4 {
+            return Err(EINVAL);
+        }
+        let u16_at = |pos: usize| u16::from_le_bytes(value[pos..pos + 2].try_into().unwrap());
+        let u32_at = |pos: usize| u32::from_le_bytes(value[pos..pos + 4].try_into().unwrap());
+        if u32_at(0) != POSIX_ACL_XATTR_VERSION {
+            return Err(EINVAL);
+        }
+        let entries = (4..value.len())
+            .step_by(ENTRY_SIZE)
+            .map(|pos| {
+                let id = u32_at(pos + 4);
+                let tag = match u16_at(pos) {
+                    0x01 => AclTag::UserObj,
+                    0x02 => AclTag::User(id),
+                    0x04 => AclTag::GroupObj,
+                    0x08 => AclTag::Group(id),
+                    0x10 => AclTag::Mask,
+                    0x20 => AclTag::Other,
+                    _ => return Err(EINVAL),
+                };
+                let perm = u16_at(pos + 2);
+                if perm & !0o7 != 0 {
+                    return Err(EINVAL);
+                }
+                Ok(AclEntry { tag, perm })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(PosixAcl { entries })
+    }
+
+    /// Encode the ACL to the value of an ACL extended attribute
+    pub fn to_xattr(&self) -> Vec<u8> {
+        let mut value = Vec::with_capacity(4 + self.entries.len() * ENTRY_SIZE);
+        value.extend_from_slice(&POSIX_ACL_XATTR_VERSION.to_le_bytes());
+        for entry in &self.entries {
+            let (tag, id): (u16, u32) = match entry.tag {
+                AclTag::UserObj => (0x01, ACL_UNDEFINED_ID),
+                AclTag::User(uid) => (0x02, uid),
+                AclTag::GroupObj => (0x04, ACL_UNDEFINED_ID),
+                AclTag::Group(gid) => (0x08, gid),
+                AclTag::Mask => (0x10, ACL_UNDEFINED_ID),
+                AclTag::Other => (0x20, ACL_UNDEFINED_ID),
+            };
+            value.extend_from_slice(&tag.to_le_bytes());
+            value.extend_from_slice(&entry.perm.to_le_bytes());
+            value.extend_from_slice(&id.to_le_bytes());
+        }
+        value
+    }
+
+    /// Permission bits of the file mode that are equivalent to this access ACL. The group
+    /// bits are taken from the mask entry if there is one.
+    pub fn mode(&self) -> u32 {
+        let perm = |tag| {
+            self.entries
+                .iter()
+                .find(|entry| entry.tag == tag)
+                .map_or(0, |entry| entry.perm as u32)
+        };
+        let group = match self.entries.iter().any(|entry| entry.tag == AclTag::Mask) {
+            true => perm(AclTag::Mask),
+            false => perm(AclTag::GroupObj),
+        };
+        perm(AclTag::UserObj) << 6 | group << 3 | perm(AclTag::Other)
+    }
+
+    /// Returns true if the ACL is equivalent to the permission bits of the file mode, i.e.
+    /// it consists only of the owner, group and other entries
+    pub fn is_minimal(&self) -> bool {
+        self.entries.iter().all(|entry| {
+            matches!(
+                entry.tag,
+                AclTag::UserObj | AclTag::GroupObj | AclTag::Other
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{AclEntry, AclTag, PosixAcl};
+    use libc::EINVAL;
+
+    #[test]
+    fn minimal() {
+        let acl = PosixAcl::from_mode(0o100754);
+        assert!(acl.is_minimal());
+        assert_eq!(acl.mode(), 0o754);
+        assert_eq!(
+            acl.to_xattr(),
+            vec![
+                0x02, 0x00, 0x00, 0x00, // version
+                0x01, 0x00, 0x07, 0x00, 0xff, 0xff, 0xff, 0xff, // user_obj
+                0x04, 0x00, 0x05, 0x00, 0xff, 0xff, 0xff, 0xff, // group_obj
+                0x20, 0x00, 0x04, 0x00, 0xff, 0xff, 0xff, 0xff, // other
+            ]
+        );
+    }
+
+    #[test]
+    fn extended() {
+        let mut acl = PosixAcl::from_mode(0o640);
+        acl.entries.insert(
+            1,
+            AclEntry {
+                tag: AclTag::User(1000),
+                perm: 0o6,
+            },
+        );
+        acl.entries.insert(
+            3,
+            AclEntry {
+                tag: AclTag::Mask,
+                perm: 0o6,
+            },
+        );
+        assert!(!acl.is_minimal());
+        assert_eq!(acl.mode(), 0o660);
+        let value = acl.to_xattr();
+        assert_eq!(value.len(), 4 + 5 * 8);
+        assert_eq!(
+            value[12..20],
+            [0x02, 0x00, 0x06, 0x00, 0xe8, 0x03, 0x00, 0x00]
+        );
+        assert_eq!(PosixAcl::from_xattr(&value), Ok(acl));
+    }
+
+    #[test]
+    fn invalid() {
+        let value = PosixAcl::from_mode(0o644).to_xattr();
+        assert_eq!(PosixAcl::from_xattr(&value[..3]), Err(EINVAL));
+        assert_eq!(PosixAcl::from_xattr(&value[..10]), Err(EINVAL));
+        let mut bad = value.clone();
+        bad[0] = 0x01; // version
+        assert_eq!(PosixAcl::from_xattr(&bad), Err(EINVAL));
+        let mut bad = value.clone();
+        bad[4] = 0x03; // tag
+        assert_eq!(PosixAcl::from_xattr(&bad), Err(EINVAL));
+        let mut bad = value;
+        bad[6] = 0x08; // perm
+        assert_eq!(PosixAcl::from_xattr(&bad), Err(EINVAL));
+        assert_eq!(PosixAcl::from_xattr(&[2, 0, 0, 0]), Ok(PosixAcl::default()));
+    }
+}
@@ -7,8 +7,12 @@ use libc::{self, c_int, c_void, size_t};
 use log::error;
 use std::ffi::{CStr, CString, OsString};
 use std::io;
+use std::mem::ManuallyDrop;
 use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd};
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, Weak};
+use tokio::io::unix::AsyncFd;
 
 use crate::reply::ReplySender;
 
@@ -25,31 +29,115 @@ fn with_fuse_args<T, F: FnOnce(&fuse_args) -> T>(options: &[OsString], f: F) ->
     })
 }
 
+/// Switch the given fd to non-blocking mode
+fn set_nonblocking(fd: c_int) -> io::Result<()> {
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    if flags < 0 || unsafe { libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Register the given fd with the tokio reactor (in non-blocking mode) and take ownership
+/// of it. The fd is closed if this fails.
+fn register(fd: c_int) -> io::Result<AsyncFd<Arc<OwnedFd>>> {
+    let fd = Arc::new(unsafe { OwnedFd::from_raw_fd(fd) });
+    set_nonblocking(fd.as_raw_fd())?;
+    AsyncFd::new(fd)
+}
+
 /// A raw communication channel to the FUSE kernel driver
 #[derive(Debug)]
 pub struct Channel {
     mountpoint: PathBuf,
-    fd: c_int,
+    /// Non-blocking fd, registered with the tokio reactor. It's deregistered
+    /// and closed explicitly on drop, before unmounting. Senders only hold a
+    /// weak reference, so they can't keep it open.
+    fd: ManuallyDrop<AsyncFd<Arc<OwnedFd>>>,
+    /// True if the mount point is unmounted on drop (i.e. it's not a cloned channel)
+    mounted: bool,
 }
 
 impl Channel {
     /// Create a new communication channel to the kernel driver by mounting the
     /// given path. The kernel driver will delegate filesystem operations of
     /// the given path to the channel. If the channel is dropped, the path is
-    /// unmounted.
+    /// unmounted. Must be called within the context of a tokio runtime.
     pub fn new(mountpoint: &Path, options: &[OsString]) -> io::Result<Channel> {
         let mountpoint = mountpoint.canonicalize()?;
-        with_fuse_args(options, |args| {
+        let fd = with_fuse_args(options, |args| {
             let mnt = CString::new(mountpoint.as_os_str().as_bytes())?;
             let fd = unsafe { fuse_mount_compat25(mnt.as_ptr(), args) };
             if fd < 0 {
                 Err(io::Error::last_os_error())
             } else {
-                Ok(Channel {
-                    mountpoint: mountpoint,
-                    fd: fd,
-                })
+                Ok(fd)
+            }
+        })?;
+        match register(fd) {
+            Ok(fd) => Ok(Channel {
+                mountpoint,
+                fd: ManuallyDrop::new(fd),
+                mounted: true,
+            }),
+            Err(err) => {
+                let _ = unmount(&mountpoint);
+                Err(err)
             }
+        }
+    }
+
+    /// Open a communication channel to the kernel driver using the given device
+    /// (e.g. /dev/cuse) without mounting anything. Must be called within the
+    /// context of a tokio runtime.
+    pub fn open(device: &Path) -> io::Result<Channel> {
+        let dev = CString::new(device.as_os_str().as_bytes())?;
+        let fd = unsafe { libc::open(dev.as_ptr(), libc::O_RDWR | libc::O_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Channel {
+            mountpoint: device.to_path_buf(),
+            fd: ManuallyDrop::new(register(fd)?),
+            mounted: false,
+        })
+    }
+
+    /// Open another communication channel to the same kernel connection (Linux
+    /// only). The kernel driver hands out every request on exactly one of the
+    /// channels and expects the reply on the channel the request was received
+    /// from. Dropping a cloned channel doesn't unmount the filesystem.
+    pub fn try_clone(&self) -> io::Result<Channel> {
+        #[cfg(target_os = "linux")]
+        fn clone_fd(fd: c_int) -> io::Result<c_int> {
+            use fuse_abi::consts::FUSE_DEV_IOC_CLONE;
+
+            let dev = CString::new("/dev/fuse").unwrap();
+            let clone = unsafe { libc::open(dev.as_ptr(), libc::O_RDWR | libc::O_CLOEXEC) };
+            if clone < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let mut fd = fd as u32;
+            if unsafe { libc::ioctl(clone, FUSE_DEV_IOC_CLONE as _, &mut fd) } < 0 {
+                let err = io::Error::last_os_error();
+                unsafe {
+                    libc::close(clone);
+                }
+                return Err(err);
+            }
+            Ok(clone)
+        }
+
+        #[cfg(not(target_os = "linux"))]
+        fn clone_fd(_fd: c_int) -> io::Result<c_int> {
+            Err(io::Error::from_raw_os_error(libc::ENOSYS))
+        }
+
+        let fd = register(clone_fd(self.fd.get_ref().as_raw_fd())?)?;
+        Ok(Channel {
+            mountpoint: self.mountpoint.clone(),
+            fd: ManuallyDrop::new(fd),
+            mounted: false,
         })
     }
 
@@ -58,22 +146,36 @@ impl Channel {
         &self.mountpoint
     }
 
-    /// Receives data up to the capacity of the given buffer (can block).
-    pub fn receive(&self, buffer: &mut Vec<u8>) -> io::Result<()> {
-        let rc = unsafe {
-            libc::read(
-                self.fd,
-                buffer.as_ptr() as *mut c_void,
-                buffer.capacity() as size_t,
-            )
-        };
-        if rc < 0 {
-            Err(io::Error::last_os_error())
-        } else {
-            unsafe {
-                buffer.set_len(rc as usize);
+    /// Receives data up to the capacity of the given buffer. Waits until the
+    /// kernel driver has a request available without blocking the thread.
+    pub async fn receive(&self, buffer: &mut Vec<u8>) -> io::Result<()> {
+        loop {
+            let mut guard = self.fd.readable().await?;
+            let res = guard.try_io(|fd| {
+                let rc = unsafe {
+                    libc::read(
+                        fd.get_ref().as_raw_fd(),
+                        buffer.as_ptr() as *mut c_void,
+                        buffer.capacity() as size_t,
+                    )
+                };
+                if rc < 0 {
+                    Err(io::Error::last_os_error())
+                } else {
+                    Ok(rc as usize)
+                }
+            });
+            match res {
+                Ok(Ok(len)) => {
+                    unsafe {
+                        buffer.set_len(len);
+                    }
+                    return Ok(());
+                }
+                Ok(Err(err)) => return Err(err),
+                // Spurious readiness, wait again
+                Err(_would_block) => continue,
             }
-            Ok(())
         }
     }
 
@@ -84,8 +186,10 @@ impl Channel {
         // Since write/writev syscalls are threadsafe, we can simply create
         // a sender by using the same fd and use it in other threads. Only
         // the channel closes the fd when dropped. If any sender is used after
-        // dropping the channel, it'll return an EBADF error.
-        ChannelSender { fd: self.fd }
+        // dropping the channel, it'll return an ENODEV error.
+        ChannelSender {
+            fd: Arc::downgrade(self.fd.get_ref()),
+        }
     }
 }
 
@@ -94,21 +198,48 @@ impl Drop for Channel {
         // TODO: send ioctl FUSEDEVIOCSETDAEMONDEAD on macOS before closing the fd
         // Close the communication channel to the kernel driver
         // (closing it before unnmount prevents sync unmount deadlock)
-        unsafe {
-            libc::close(self.fd);
-        }
+        // Senders only hold the fd for the duration of a single write or ioctl
+        drop(unsafe { ManuallyDrop::take(&mut self.fd) }.into_inner());
         // Unmount this channel's mount point
-        let _ = unmount(&self.mountpoint);
+        if self.mounted {
+            let _ = unmount(&self.mountpoint);
+        }
     }
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Debug)]
 pub struct ChannelSender {
-    fd: c_int,
+    /// Fd of the channel, which is closed when the channel is dropped
+    fd: Weak<OwnedFd>,
 }
 
 impl ChannelSender {
-    /// Send all data in the slice of slice of bytes in a single write (can block).
+    /// Create a sender for an arbitrary fd (for testing)
+    #[cfg(test)]
+    pub fn from_fd(fd: &Arc<OwnedFd>) -> Self {
+        ChannelSender {
+            fd: Arc::downgrade(fd),
+        }
+    }
+
+    /// Create a sender for a channel that has already been dropped (for testing)
+    #[cfg(test)]
+    pub fn closed() -> Self {
+        ChannelSender { fd: Weak::new() }
+    }
+
+    /// Call the given function with the fd of the channel, which is kept open meanwhile.
+    /// Fails with ENODEV if the channel was dropped, i.e. the session ended.
+    fn with_fd<T, F: FnOnce(c_int) -> io::Result<T>>(&self, f: F) -> io::Result<T> {
+        match self.fd.upgrade() {
+            Some(fd) => f(fd.as_raw_fd()),
+            None => Err(io::Error::from_raw_os_error(libc::ENODEV)),
+        }
+    }
+
+    /// Send all data in the slice of slice of bytes in a single write. Writes to the
+    /// kernel driver are processed immediately, so this doesn't block even though
+    /// the fd is in non-blocking mode.
     pub fn send(&self, buffer: &[&[u8]]) -> io::Result<()> {
         let iovecs: Vec<_> = buffer
             .iter()
@@ -117,11 +248,64 @@ impl ChannelSender {
                 iov_len: d.len() as size_t,
             })
             .collect();
-        let rc = unsafe { libc::writev(self.fd, iovecs.as_ptr(), iovecs.len() as c_int) };
-        if rc < 0 {
-            Err(io::Error::last_os_error())
-        } else {
-            Ok(())
+        self.with_fd(|fd| {
+            let rc = unsafe { libc::writev(fd, iovecs.as_ptr(), iovecs.len() as c_int) };
+            if rc < 0 {
+                Err(io::Error::last_os_error())
+            } else {
+                Ok(())
+            }
+        })
+    }
+
+    /// Register the given file as backing file for passthrough I/O (Linux only). Returns
+    /// the backing id the kernel assigned to it. Needs the `CAP_SYS_ADMIN` capability.
+    pub fn open_backing(&self, fd: c_int) -> io::Result<u32> {
+        #[cfg(target_os = "linux")]
+        {
+            use fuse_abi::consts::FUSE_DEV_IOC_BACKING_OPEN;
+            use fuse_abi::fuse_backing_map;
+
+            let map = fuse_backing_map {
+                fd,
+                flags: 0,
+                padding: 0,
+            };
+            self.with_fd(|dev| {
+                let rc = unsafe { libc::ioctl(dev, FUSE_DEV_IOC_BACKING_OPEN as _, &map) };
+                if rc < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(rc as u32)
+            })
+        }
+
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = fd;
+            Err(io::Error::from_raw_os_error(libc::ENOSYS))
+        }
+    }
+
+    /// Unregister the backing file with the given id (Linux only). Files that were opened
+    /// with it keep using it until they're released.
+    pub fn close_backing(&self, id: u32) -> io::Result<()> {
+        #[cfg(target_os = "linux")]
+        {
+            use fuse_abi::consts::FUSE_DEV_IOC_BACKING_CLOSE;
+
+            self.with_fd(|dev| {
+                if unsafe { libc::ioctl(dev, FUSE_DEV_IOC_BACKING_CLOSE as _, &id) } < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            })
+        }
+
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = id;
+            Err(io::Error::from_raw_os_error(libc::ENOSYS))
         }
     }
 }
@@ -148,7 +332,6 @@ pub fn unmount(mountpoint: &Path) -> io::Result<()> {
         target_os = "freebsd",
         target_os = "dragonfly",
         target_os = "openbsd",
-        target_os = "bitrig",
         target_os = "netbsd"
     ))]
     #[inline]
@@ -161,7 +344,6 @@ pub fn unmount(mountpoint: &Path) -> io::Result<()> {
         target_os = "freebsd",
         target_os = "dragonfly",
         target_os = "openbsd",
-        target_os = "bitrig",
         target_os = "netbsd"
     )))]
     #[inline]
@@ -193,8 +375,25 @@ pub fn unmount(mountpoint: &Path) -> io::Result<()> {
 
 #[cfg(test)]
 mod test {
-    use super::with_fuse_args;
+    use super::{with_fuse_args, ChannelSender};
     use std::ffi::{CStr, OsString};
+    use std::os::unix::io::{FromRawFd, OwnedFd};
+    use std::sync::Arc;
+
+    #[test]
+    fn send_after_close() {
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        let reader = unsafe { OwnedFd::from_raw_fd(fds[0]) };
+        let fd = Arc::new(unsafe { OwnedFd::from_raw_fd(fds[1]) });
+        let sender = ChannelSender::from_fd(&fd);
+        assert!(sender.send(&[b"foo"]).is_ok());
+        // Once the channel closed its fd, senders must not write to whatever reuses it
+        drop(fd);
+        let err = sender.send(&[b"bar"]).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::ENODEV));
+        drop(reader);
+    }
 
     #[test]
     fn fuse_args() {
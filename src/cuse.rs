@@ -0,0 +1,208 @@
+//! Character devices in userspace (CUSE)
+//!
+//! CUSE uses the FUSE protocol to implement character devices. Instead of mounting a
+//! filesystem, a session opens /dev/cuse and tells the kernel driver the name and number of
+//! the device to create. Operations on the device are then sent as FUSE requests.
+
+use async_trait::async_trait;
+use fuse_abi::FUSE_KERNEL_MINOR_VERSION;
+use libc::{c_int, ENOSYS};
+use log::info;
+use std::ffi::CString;
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU32};
+use std::sync::Arc;
+
+use crate::channel::Channel;
+use crate::interrupt::Interrupts;
+use crate::request::Request;
+use crate::session::receive_loop;
+use crate::{
+    OpenOptions, PollHandle, ReplyData, ReplyEmpty, ReplyIoctl, ReplyOpen, ReplyPoll, ReplyWrite,
+};
+
+/// Character device trait.
+///
+/// This trait must be implemented to provide a character device via CUSE. Operations get the
+/// file handle set by `open`. Reasonable default implementations are provided here to get a
+/// device that does nothing.
+#[async_trait]
+#[allow(clippy::too_many_arguments)]
+pub trait CharDevice {
+    /// Initialize device.
+    /// Called before any other device method.
+    async fn init(&self, _req: &Request) -> Result<(), c_int> {
+        Ok(())
+    }
+
+    /// Open the device.
+    /// Open flags (with the exception of O_CREAT, O_EXCL and O_NOCTTY) are available in
+    /// flags. The device may store an arbitrary file handle in fh, which is passed to all
+    /// other operations on the opened device.
+    async fn open(&self, _req: &Request, _flags: u32, reply: ReplyOpen) {
+        reply.opened(0, OpenOptions::empty());
+    }
+
+    /// Read data.
+    /// The reply may contain less data than requested, which is returned to the reader.
+    async fn read(&self, _req: &Request, _fh: u64, _offset: i64, _size: u32, reply: ReplyData) {
+        reply.error(ENOSYS);
+    }
+
+    /// Write data.
+    /// The number of bytes written is returned to the writer.
+    async fn write(
+        &self,
+        _req: &Request,
+        _fh: u64,
+        _offset: i64,
+        _data: &[u8],
+        _flags: u32,
+        reply: ReplyWrite,
+    ) {
+        reply.error(ENOSYS);
+    }
+
+    /// Release an opened device.
+    /// Called when all file descriptors of an open call are closed.
+    async fn release(&self, _req: &Request, _fh: u64, _flags: u32, reply: ReplyEmpty) {
+        reply.ok();
+    }
+
+    /// Control device.
+    /// See `Filesystem::ioctl`. Ioctls are unrestricted if the session was configured so
+    /// (see `CuseSession::set_unrestricted_ioctl`).
+    async fn ioctl(
+        &self,
+        _req: &Request,
+        _fh: u64,
+        _flags: u32,
+        _cmd: u32,
+        _arg: u64,
+        _in_data: &[u8],
+        _out_size: u32,
+        reply: ReplyIoctl,
+    ) {
+        reply.error(ENOSYS);
+    }
+
+    /// Poll for IO readiness events.
+    /// See `Filesystem::poll`.
+    async fn poll(
+        &self,
+        _req: &Request,
+        _fh: u64,
+        _ph: Option<PollHandle>,
+        _flags: u32,
+        reply: ReplyPoll,
+    ) {
+        reply.error(ENOSYS);
+    }
+}
+
+/// The CUSE session data structure
+#[derive(Debug)]
+pub struct CuseSession<D: CharDevice + Send + Sync + 'static> {
+    /// Device operation implementations
+    pub device: D,
+    /// Communication channel to the kernel driver
+    ch: Channel,
+    /// Device info sent to the kernel on initialization
+    devinfo: CString,
+    /// Major device number (0 for dynamic allocation)
+    pub(crate) dev_major: u32,
+    /// Minor device number
+    pub(crate) dev_minor: u32,
+    /// True if ioctls are unrestricted
+    pub(crate) unrestricted_ioctl: bool,
+    /// FUSE protocol major version
+    pub proto_major: AtomicU32,
+    /// FUSE protocol minor version, negotiated with the kernel on init
+    pub proto_minor: AtomicU32,
+    /// True if the device is initialized (init operation done)
+    pub initialized: AtomicBool,
+    /// Requests currently being processed (to handle interrupts)
+    pub(crate) interrupts: Interrupts,
+}
+
+impl<D: CharDevice + Send + Sync + 'static> CuseSession<D> {
+    /// Create a new session by opening /dev/cuse. The character device /dev/`name` with the
+    /// given device numbers is created once the session runs. A major number of 0 lets the
+    /// kernel choose one. Must be called within the context of a tokio runtime.
+    pub fn new(
+        device: D,
+        name: &str,
+        dev_major: u32,
+        dev_minor: u32,
+    ) -> io::Result<CuseSession<D>> {
+        let devinfo = device_info(name)?;
+        info!("Creating character device {}", name);
+        Channel::open(Path::new("/dev/cuse")).map(|ch| CuseSession {
+            device,
+            ch,
+            devinfo,
+            dev_major,
+            dev_minor,
+            unrestricted_ioctl: false,
+            proto_major: AtomicU32::new(0),
+            proto_minor: AtomicU32::new(FUSE_KERNEL_MINOR_VERSION),
+            initialized: AtomicBool::new(false),
+            interrupts: Interrupts::default(),
+        })
+    }
+
+    /// Let the device handle unrestricted ioctls, which can be retried with memory areas
+    /// of the caller (see `ReplyIoctl::retry`)
+    pub fn set_unrestricted_ioctl(&mut self, unrestricted: bool) {
+        self.unrestricted_ioctl = unrestricted;
+    }
+
+    /// Device info sent to the kernel on initialization
+    pub(crate) fn devinfo(&self) -> &[u8] {
+        self.devinfo.as_bytes_with_nul()
+    }
+
+    /// Run the session loop that receives kernel requests and dispatches them to method
+    /// calls into the device. Every request is dispatched in its own task, so device methods
+    /// run concurrently. The device is removed when the session ends.
+    pub async fn run(self) -> io::Result<()> {
+        let se = Arc::new(self);
+        receive_loop(&se.ch, &se.interrupts, &se.proto_minor, None, |req| {
+            let se = se.clone();
+            tokio::spawn(async move {
+                let unique = req.interruptible().then(|| req.unique());
+                req.dispatch_cuse(se.clone()).await;
+                if let Some(unique) = unique {
+                    se.interrupts.unregister(unique);
+                }
+            });
+        })
+        .await
+    }
+}
+
+/// Device info for the given device name
+fn device_info(name: &str) -> io::Result<CString> {
+    if name.is_empty() || name.contains('/') {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "invalid device name",
+        ));
+    }
+    Ok(CString::new(format!("DEVNAME={}", name))?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::device_info;
+
+    #[test]
+    fn devinfo() {
+        let devinfo = device_info("foo").unwrap();
+        assert_eq!(devinfo.as_bytes_with_nul(), b"DEVNAME=foo\0");
+        assert!(device_info("").is_err());
+        assert!(device_info("foo/bar").is_err());
+        assert!(device_info("foo\0").is_err());
+    }
+}
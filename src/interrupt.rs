@@ -0,0 +1,153 @@
+//! Request interruption
+//!
+//! The kernel driver sends a FUSE_INTERRUPT request if the process that triggered an operation
+//! receives a signal while waiting for the reply. The interrupted request is identified by its
+//! unique id. Since requests are dispatched concurrently, the interrupt may be received before
+//! the request it refers to. Such early interrupts are remembered until the request arrives.
+//! If it doesn't, the interrupt is answered with EAGAIN, which makes the kernel driver requeue
+//! it (or ignore the reply, if the original request has been answered meanwhile).
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+
+/// Interruption state of a single request
+#[derive(Debug, Default)]
+pub struct Interrupt {
+    /// True if the kernel asked to interrupt the request
+    interrupted: AtomicBool,
+    /// Wakes up tasks waiting for the interruption
+    notify: Notify,
+}
+
+impl Interrupt {
+    /// Mark the request as interrupted and wake up all waiting tasks
+    fn set(&self) {
+        self.interrupted.store(true, Ordering::SeqCst);
+        self.notify.notify_waiters();
+    }
+
+    /// Returns true if the request was interrupted
+    pub fn is_set(&self) -> bool {
+        self.interrupted.load(Ordering::SeqCst)
+    }
+
+    /// Wait until the request is interrupted
+    pub async fn wait(&self) {
+        loop {
+            // The notification future needs to exist before checking the flag,
+            // otherwise a wakeup in between would be lost
+            let notified = self.notify.notified();
+            if self.is_set() {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+/// Interrupts that were received before the request they refer to
+#[derive(Debug)]
+struct EarlyInterrupt {
+    /// Unique id of the request to interrupt
+    target: u64,
+    /// Unique id of the FUSE_INTERRUPT request itself
+    unique: u64,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    /// Requests currently being processed
+    inflight: HashMap<u64, Arc<Interrupt>>,
+    /// Interrupts whose target request has not been seen yet
+    early: VecDeque<EarlyInterrupt>,
+}
+
+/// Table of in-flight requests that can be interrupted
+#[derive(Debug, Default)]
+pub struct Interrupts {
+    inner: Mutex<Inner>,
+}
+
+impl Interrupts {
+    /// Register a request as being in flight. Returns its interruption state, which is already
+    /// set if an interrupt for it arrived early. If there are other early interrupts pending,
+    /// the unique id of the oldest one is returned as well and it should be replied with EAGAIN.
+    pub fn register(&self, unique: u64) -> (Arc<Interrupt>, Option<u64>) {
+        let mut inner = self.inner.lock().unwrap();
+        let interrupt = Arc::new(Interrupt::default());
+        let mut stale = None;
+        if let Some(pos) = inner.early.iter().position(|e| e.target == unique) {
+            inner.early.remove(pos);
+            interrupt.set();
+        } else if let Some(early) = inner.early.pop_front() {
+            stale = Some(early.unique);
+        }
+        inner.inflight.insert(unique, interrupt.clone());
+        (interrupt, stale)
+    }
+
+    /// Remove a request that has been completed
+    pub fn unregister(&self, unique: u64) {
+        self.inner.lock().unwrap().inflight.remove(&unique);
+    }
+
+    /// Interrupt the request with the given unique id. If the request isn't in flight, the
+    /// interrupt is remembered until it arrives.
+    pub fn interrupt(&self, target: u64, unique: u64) {
+        let mut inner = self.inner.lock().unwrap();
+        match inner.inflight.get(&target) {
+            Some(interrupt) => interrupt.set(),
+            None => inner.early.push_back(EarlyInterrupt { target, unique }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Interrupts;
+
+    #[test]
+    fn interrupt_inflight() {
+        let interrupts = Interrupts::default();
+        let (interrupt, stale) = interrupts.register(1);
+        assert!(stale.is_none());
+        assert!(!interrupt.is_set());
+        interrupts.interrupt(1, 2);
+        assert!(interrupt.is_set());
+    }
+
+    #[test]
+    fn interrupt_early() {
+        let interrupts = Interrupts::default();
+        interrupts.interrupt(1, 2);
+        let (interrupt, stale) = interrupts.register(1);
+        assert!(stale.is_none());
+        assert!(interrupt.is_set());
+    }
+
+    #[test]
+    fn interrupt_unknown() {
+        let interrupts = Interrupts::default();
+        let (_, stale) = interrupts.register(1);
+        interrupts.unregister(1);
+        interrupts.interrupt(1, 2);
+        let (interrupt, stale2) = interrupts.register(3);
+        assert!(stale.is_none());
+        assert_eq!(stale2, Some(2));
+        assert!(!interrupt.is_set());
+        let (_, stale3) = interrupts.register(4);
+        assert!(stale3.is_none());
+    }
+
+    #[tokio::test]
+    async fn wait_interrupted() {
+        let interrupts = Interrupts::default();
+        let (interrupt, _) = interrupts.register(1);
+        let waiter = tokio::spawn(async move { interrupt.wait().await });
+        tokio::task::yield_now().await;
+        interrupts.interrupt(1, 2);
+        waiter.await.unwrap();
+    }
+}
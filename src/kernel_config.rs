@@ -0,0 +1,365 @@
+//! Kernel capability negotiation
+//!
+//! On initialization, the kernel driver reports the capabilities it supports. The filesystem
+//! can choose which of them to use and tune some parameters of the connection, before the
+//! library replies to the kernel.
+
+use fuse_abi::consts::*;
+use fuse_abi::fuse_init_in;
+
+use crate::session::MAX_WRITE_SIZE;
+
+/// Capabilities requested by default
+#[cfg(not(target_os = "macos"))]
+const DEFAULT_FLAGS: u64 = FUSE_ASYNC_READ | FUSE_SETXATTR_EXT;
+
+/// On macOS, we additionally support case insensitiveness, volume renames and xtimes
+#[cfg(target_os = "macos")]
+const DEFAULT_FLAGS: u64 = FUSE_ASYNC_READ | FUSE_CASE_INSENSITIVE | FUSE_VOL_RENAME | FUSE_XTIMES;
+
+/// Maximum stacking depth of filesystems supported by the kernel
+const FILESYSTEM_MAX_STACK_DEPTH: u32 = 2;
+
+/// Configuration of the connection to the kernel driver, passed to `Filesystem::init`
+#[derive(Debug)]
+pub struct KernelConfig {
+    /// Capabilities offered by the kernel
+    offered: u64,
+    /// Capabilities requested by the filesystem
+    requested: u64,
+    /// Maximum readahead size offered by the kernel
+    max_max_readahead: u32,
+    /// Maximum readahead size
+    max_readahead: u32,
+    /// Maximum size of write requests
+    max_write: u32,
+    /// Maximum number of pending background requests
+    max_background: u16,
+    /// Number of pending background requests at which the kernel considers the filesystem congested
+    congestion_threshold: u16,
+    /// Timestamp granularity in nanoseconds
+    time_gran: u32,
+    /// Maximum number of pages per request
+    max_pages: u16,
+    /// Alignment of DAX mappings (log2 of the alignment in bytes)
+    map_alignment: u16,
+    /// Maximum stacking depth of backing files for passthrough I/O
+    max_stack_depth: u32,
+}
+
+/// Capabilities offered in the given init request, including the extended flags
+fn offered_flags(arg: &fuse_init_in) -> u64 {
+    let flags = arg.flags as u64;
+    // The kernel only sends flags2 if FUSE_INIT_EXT is set
+    #[cfg(not(target_os = "macos"))]
+    if flags & FUSE_INIT_EXT != 0 {
+        return flags | (arg.flags2 as u64) << 32;
+    }
+    flags
+}
+
+/// Number of pages needed for requests of the given size
+fn pages(size: u32) -> u16 {
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) }.max(1) as u32;
+    size.div_ceil(page_size).min(u16::MAX as u32) as u16
+}
+
+impl KernelConfig {
+    /// Create a configuration with default settings from the kernel's init request
+    pub(crate) fn new(arg: &fuse_init_in) -> Self {
+        let offered = offered_flags(arg);
+        // Let the kernel send write requests as large as max_write if it supports that
+        let requested = offered & (DEFAULT_FLAGS | FUSE_MAX_PAGES);
+        Self {
+            offered,
+            requested,
+            max_max_readahead: arg.max_readahead,
+            max_readahead: arg.max_readahead, // accept any readahead size
+            max_write: MAX_WRITE_SIZE as u32, // use a max write size that fits into the session's buffer
+            max_background: 32,
+            congestion_threshold: 30,
+            time_gran: 1,
+            max_pages: pages(MAX_WRITE_SIZE as u32),
+            map_alignment: 0,
+            max_stack_depth: 1,
+        }
+    }
+
+    /// Capabilities (`FUSE_*` init flags) offered by the kernel. Flags above bit 31 are
+    /// offered in the extended flags word (flags2) of kernels with ABI 7.36 and later.
+    pub fn offered(&self) -> u64 {
+        self.offered
+    }
+
+    /// Capabilities (`FUSE_*` init flags) that will be requested from the kernel
+    pub fn capabilities(&self) -> u64 {
+        self.requested
+    }
+
+    /// Capabilities to reply with, split into the flags and flags2 words. The kernel only
+    /// reads flags2 if FUSE_INIT_EXT is set, which is done if the kernel offered it.
+    pub(crate) fn reply_flags(&self) -> (u32, u32) {
+        #[allow(unused_mut)]
+        let mut flags = self.requested & self.offered;
+        #[cfg(not(target_os = "macos"))]
+        {
+            flags |= self.offered & FUSE_INIT_EXT;
+        }
+        (flags as u32, (flags >> 32) as u32)
+    }
+
+    /// Request the given capabilities, e.g. `FUSE_POSIX_LOCKS | FUSE_FLOCK_LOCKS`. If the kernel
+    /// didn't offer all of them, nothing is changed and the unsupported flags are returned.
+    pub fn add_capabilities(&mut self, flags: u64) -> Result<(), u64> {
+        let unsupported = flags & !self.offered;
+        if unsupported != 0 {
+            return Err(unsupported);
+        }
+        self.requested |= flags;
+        Ok(())
+    }
+
+    /// Don't request the given capabilities
+    pub fn remove_capabilities(&mut self, flags: u64) {
+        self.requested &= !flags;
+    }
+
+    /// Maximum readahead size in bytes
+    pub fn max_readahead(&self) -> u32 {
+        self.max_readahead
+    }
+
+    /// Set the maximum readahead size in bytes. It can't exceed the size offered by the kernel,
+    /// which is returned as error if it does. Returns the previous value on success.
+    pub fn set_max_readahead(&mut self, value: u32) -> Result<u32, u32> {
+        if value > self.max_max_readahead {
+            return Err(self.max_max_readahead);
+        }
+        Ok(std::mem::replace(&mut self.max_readahead, value))
+    }
+
+    /// Maximum size of write requests in bytes
+    pub fn max_write(&self) -> u32 {
+        self.max_write
+    }
+
+    /// Set the maximum size of write requests in bytes. It must be non-zero and can't exceed
+    /// the size the session is able to receive, which is returned as error if it does. Sizes
+    /// larger than 4 KiB additionally need the `FUSE_BIG_WRITES` capability on older kernels.
+    /// Returns the previous value on success.
+    pub fn set_max_write(&mut self, value: u32) -> Result<u32, u32> {
+        if value == 0 || value > MAX_WRITE_SIZE as u32 {
+            return Err(MAX_WRITE_SIZE as u32);
+        }
+        Ok(std::mem::replace(&mut self.max_write, value))
+    }
+
+    /// Maximum number of pending background requests
+    pub fn max_background(&self) -> u16 {
+        self.max_background
+    }
+
+    /// Set the maximum number of pending background requests (readahead and asynchronous
+    /// direct I/O requests). It must be non-zero, else the smallest valid value is returned
+    /// as error. Returns the previous value on success.
+    pub fn set_max_background(&mut self, value: u16) -> Result<u16, u16> {
+        if value == 0 {
+            return Err(1);
+        }
+        Ok(std::mem::replace(&mut self.max_background, value))
+    }
+
+    /// Number of pending background requests at which the kernel considers the filesystem
+    /// congested
+    pub fn congestion_threshold(&self) -> u16 {
+        self.congestion_threshold
+    }
+
+    /// Set the number of pending background requests at which the kernel considers the
+    /// filesystem congested. It can't exceed the maximum number of background requests,
+    /// which is returned as error if it does. Returns the previous value on success.
+    pub fn set_congestion_threshold(&mut self, value: u16) -> Result<u16, u16> {
+        if value > self.max_background {
+            return Err(self.max_background);
+        }
+        Ok(std::mem::replace(&mut self.congestion_threshold, value))
+    }
+
+    /// Timestamp granularity in nanoseconds
+    pub fn time_gran(&self) -> u32 {
+        self.time_gran
+    }
+
+    /// Set the granularity of timestamps the filesystem supports in nanoseconds (e.g.
+    /// 1000000000 if timestamps are stored in whole seconds). It must be non-zero and can't
+    /// exceed one second, which is returned as error if it does. Returns the previous value
+    /// on success.
+    pub fn set_time_gran(&mut self, value: u32) -> Result<u32, u32> {
+        if value == 0 || value > 1_000_000_000 {
+            return Err(1_000_000_000);
+        }
+        Ok(std::mem::replace(&mut self.time_gran, value))
+    }
+
+    /// Maximum number of pages per request
+    pub fn max_pages(&self) -> u16 {
+        self.max_pages
+    }
+
+    /// Set the maximum number of pages per request, which limits the size of read and write
+    /// requests. It's only used with the `FUSE_MAX_PAGES` capability (requested by default if
+    /// offered) and the kernel may limit it further. By default, it fits the maximum write
+    /// size. It must be non-zero, else the smallest valid value is returned as error.
+    /// Returns the previous value on success.
+    pub fn set_max_pages(&mut self, value: u16) -> Result<u16, u16> {
+        if value == 0 {
+            return Err(1);
+        }
+        Ok(std::mem::replace(&mut self.max_pages, value))
+    }
+
+    /// Alignment of DAX mappings (log2 of the alignment in bytes)
+    pub fn map_alignment(&self) -> u16 {
+        self.map_alignment
+    }
+
+    /// Set the alignment of DAX mapping offsets as log2 of the alignment in bytes (e.g. 12
+    /// for 4 KiB). It's only used with the `FUSE_MAP_ALIGNMENT` capability. Returns the
+    /// previous value.
+    pub fn set_map_alignment(&mut self, value: u16) -> u16 {
+        std::mem::replace(&mut self.map_alignment, value)
+    }
+
+    /// Maximum stacking depth of backing files for passthrough I/O
+    pub fn max_stack_depth(&self) -> u32 {
+        self.max_stack_depth
+    }
+
+    /// Set how deep filesystems may be stacked on backing files for passthrough I/O, where
+    /// 1 means that backing files must not be on a stacked filesystem (like another FUSE
+    /// filesystem using passthrough) themselves. It's only used with the `FUSE_PASSTHROUGH`
+    /// capability, which the kernel doesn't enable together with `FUSE_WRITEBACK_CACHE`.
+    /// It must be 1 or 2, else the largest valid value is returned as error. Returns the
+    /// previous value on success.
+    pub fn set_max_stack_depth(&mut self, value: u32) -> Result<u32, u32> {
+        if value == 0 || value > FILESYSTEM_MAX_STACK_DEPTH {
+            return Err(FILESYSTEM_MAX_STACK_DEPTH);
+        }
+        Ok(std::mem::replace(&mut self.max_stack_depth, value))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::KernelConfig;
+    use fuse_abi::consts::*;
+    use fuse_abi::fuse_init_in;
+
+    fn config() -> KernelConfig {
+        KernelConfig::new(&fuse_init_in {
+            major: 7,
+            minor: 19,
+            max_readahead: 131072,
+            flags: (FUSE_ASYNC_READ | FUSE_POSIX_LOCKS) as u32,
+            flags2: 0,
+            unused: [0; 11],
+        })
+    }
+
+    #[test]
+    fn capabilities() {
+        let mut config = config();
+        assert_eq!(config.capabilities() & FUSE_ASYNC_READ, FUSE_ASYNC_READ);
+        assert_eq!(config.add_capabilities(FUSE_POSIX_LOCKS), Ok(()));
+        assert_eq!(
+            config.add_capabilities(1 << 10 | FUSE_ASYNC_READ),
+            Err(1 << 10)
+        );
+        config.remove_capabilities(FUSE_ASYNC_READ);
+        assert_eq!(config.capabilities(), FUSE_POSIX_LOCKS);
+    }
+
+    #[test]
+    fn limits() {
+        let mut config = config();
+        assert_eq!(config.set_max_readahead(4096), Ok(131072));
+        assert_eq!(config.set_max_readahead(262144), Err(131072));
+        assert_eq!(config.max_readahead(), 4096);
+        assert!(config.set_max_write(0).is_err());
+        assert!(config.set_max_write(u32::MAX).is_err());
+        assert!(config.set_max_write(65536).is_ok());
+        assert_eq!(config.max_write(), 65536);
+    }
+
+    #[test]
+    fn background() {
+        let mut config = config();
+        assert_eq!(config.set_max_background(0), Err(1));
+        assert_eq!(config.set_max_background(16), Ok(32));
+        assert_eq!(config.set_congestion_threshold(20), Err(16));
+        assert_eq!(config.set_congestion_threshold(12), Ok(30));
+    }
+
+    #[cfg(not(target_os = "macos"))]
+    #[test]
+    fn extended_capabilities() {
+        let mut config = KernelConfig::new(&fuse_init_in {
+            major: 7,
+            minor: 36,
+            max_readahead: 131072,
+            flags: (FUSE_ASYNC_READ | FUSE_INIT_EXT) as u32,
+            flags2: (FUSE_SECURITY_CTX >> 32) as u32,
+            unused: [0; 11],
+        });
+        assert_eq!(
+            config.offered(),
+            FUSE_ASYNC_READ | FUSE_INIT_EXT | FUSE_SECURITY_CTX
+        );
+        assert_eq!(config.reply_flags(), (FUSE_ASYNC_READ as u32 | 1 << 30, 0));
+        assert_eq!(config.add_capabilities(FUSE_SECURITY_CTX), Ok(()));
+        assert_eq!(config.reply_flags(), (FUSE_ASYNC_READ as u32 | 1 << 30, 1));
+    }
+
+    #[cfg(not(target_os = "macos"))]
+    #[test]
+    fn setxattr_ext() {
+        let mut config = KernelConfig::new(&fuse_init_in {
+            major: 7,
+            minor: 33,
+            max_readahead: 131072,
+            flags: (FUSE_POSIX_ACL | FUSE_SETXATTR_EXT) as u32,
+            flags2: 0,
+            unused: [0; 11],
+        });
+        // The extended setxattr request is used by default, POSIX ACLs only if requested
+        assert_eq!(config.reply_flags(), (FUSE_SETXATTR_EXT as u32, 0));
+        assert_eq!(config.add_capabilities(FUSE_POSIX_ACL), Ok(()));
+        assert_eq!(
+            config.reply_flags(),
+            ((FUSE_POSIX_ACL | FUSE_SETXATTR_EXT) as u32, 0)
+        );
+        config.remove_capabilities(FUSE_SETXATTR_EXT);
+        assert_eq!(config.reply_flags(), (FUSE_POSIX_ACL as u32, 0));
+    }
+
+    #[test]
+    fn granularity() {
+        let mut config = config();
+        assert_eq!(config.set_time_gran(0), Err(1_000_000_000));
+        assert_eq!(config.set_time_gran(1_000), Ok(1));
+        assert_eq!(config.time_gran(), 1_000);
+        assert!(config.max_pages() > 0);
+        assert_eq!(config.set_max_pages(0), Err(1));
+        assert!(config.set_max_pages(32).is_ok());
+        assert_eq!(config.max_pages(), 32);
+    }
+
+    #[test]
+    fn stack_depth() {
+        let mut config = config();
+        assert_eq!(config.max_stack_depth(), 1);
+        assert_eq!(config.set_max_stack_depth(0), Err(2));
+        assert_eq!(config.set_max_stack_depth(3), Err(2));
+        assert_eq!(config.set_max_stack_depth(2), Ok(1));
+    }
+}
@@ -15,18 +15,30 @@ use std::io;
 use std::path::Path;
 use std::time::SystemTime;
 
+pub use acl::{AclEntry, AclTag, PosixAcl, XATTR_POSIX_ACL_ACCESS, XATTR_POSIX_ACL_DEFAULT};
+pub use cuse::{CharDevice, CuseSession};
 pub use fuse_abi::consts;
 pub use fuse_abi::FUSE_ROOT_ID;
+pub use kernel_config::KernelConfig;
+pub use notify::{Notifier, PollHandle};
+pub use passthrough::BackingId;
 #[cfg(target_os = "macos")]
 pub use reply::ReplyXTimes;
 pub use reply::ReplyXattr;
 pub use reply::{Reply, ReplyAttr, ReplyData, ReplyEmpty, ReplyEntry, ReplyOpen};
 pub use reply::{ReplyBmap, ReplyCreate, ReplyDirectory, ReplyLock, ReplyStatfs, ReplyWrite};
+pub use reply::{ReplyDirectoryPlus, ReplyIoctl, ReplyLseek, ReplyPoll, ReplyStatx};
 pub use request::Request;
 pub use session::{BackgroundSession, Session};
 
+mod acl;
 mod channel;
+mod cuse;
+mod interrupt;
+mod kernel_config;
 mod ll;
+mod notify;
+mod passthrough;
 mod reply;
 mod request;
 mod session;
@@ -83,17 +95,174 @@ pub struct FileAttr {
     pub flags: u32,
 }
 
+/// A time to set with setattr
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum TimeOrNow {
+    /// The given time
+    SpecificTime(SystemTime),
+    /// The current time of the filesystem (e.g. `utimensat` with `UTIME_NOW`)
+    Now,
+}
+
+/// Define a set of flags as a newtype of the raw `u32` value with the given constants.
+/// Unknown flags are preserved, so that they can be rejected or passed on.
+macro_rules! flags {
+    (
+        $(#[$attr:meta])*
+        pub struct $name:ident {
+            $(
+                $(#[$flag_attr:meta])*
+                const $flag:ident = $value:expr;
+            )*
+        }
+    ) => {
+        $(#[$attr])*
+        #[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+        pub struct $name(u32);
+
+        impl $name {
+            $(
+                $(#[$flag_attr])*
+                pub const $flag: $name = $name($value);
+            )*
+
+            /// No flags
+            pub fn empty() -> $name {
+                $name(0)
+            }
+
+            /// Create flags from the raw value
+            pub fn from_bits(bits: u32) -> $name {
+                $name(bits)
+            }
+
+            /// Raw value
+            pub fn bits(self) -> u32 {
+                self.0
+            }
+
+            /// Returns true if no flags are set
+            pub fn is_empty(self) -> bool {
+                self.0 == 0
+            }
+
+            /// Returns true if all of the given flags are set
+            pub fn contains(self, other: $name) -> bool {
+                self.0 & other.0 == other.0
+            }
+        }
+
+        impl std::ops::BitOr for $name {
+            type Output = $name;
+
+            fn bitor(self, rhs: $name) -> $name {
+                $name(self.0 | rhs.0)
+            }
+        }
+    };
+}
+
+flags! {
+    /// Mode flags of fallocate requests (see fallocate(2)). Flags unknown to this library are
+    /// preserved, so that filesystems can reject them.
+    pub struct FallocateFlags {
+        /// Don't change the file size, even if allocating beyond it (FALLOC_FL_KEEP_SIZE)
+        const KEEP_SIZE = 0x01;
+        /// Deallocate the range, must be combined with KEEP_SIZE (FALLOC_FL_PUNCH_HOLE)
+        const PUNCH_HOLE = 0x02;
+        /// Remove the range and shift the following data down (FALLOC_FL_COLLAPSE_RANGE)
+        const COLLAPSE_RANGE = 0x08;
+        /// Zero the range, allocating it if necessary (FALLOC_FL_ZERO_RANGE)
+        const ZERO_RANGE = 0x10;
+    }
+}
+
+impl FallocateFlags {
+    /// Returns the flags that aren't known to this library
+    pub fn unknown(self) -> u32 {
+        let known = Self::KEEP_SIZE | Self::PUNCH_HOLE | Self::COLLAPSE_RANGE | Self::ZERO_RANGE;
+        self.0 & !known.0
+    }
+}
+
+flags! {
+    /// Flags of an opened file or directory, which tell the kernel how to handle it. Each flag
+    /// needs a minimum version of the FUSE protocol, see `unsupported`.
+    pub struct OpenOptions {
+        /// Bypass the page cache for this open file (FOPEN_DIRECT_IO)
+        const DIRECT_IO = consts::FOPEN_DIRECT_IO;
+        /// Don't invalidate the data cache on open (FOPEN_KEEP_CACHE)
+        const KEEP_CACHE = consts::FOPEN_KEEP_CACHE;
+        /// The file is not seekable (FOPEN_NONSEEKABLE, ABI 7.10)
+        const NONSEEKABLE = consts::FOPEN_NONSEEKABLE;
+        /// Allow caching the entries of this directory (FOPEN_CACHE_DIR, ABI 7.28)
+        const CACHE_DIR = consts::FOPEN_CACHE_DIR;
+        /// The file is stream-like and has no file position at all (FOPEN_STREAM, ABI 7.31)
+        const STREAM = consts::FOPEN_STREAM;
+        /// Don't flush the data cache on close (FOPEN_NOFLUSH, ABI 7.32)
+        const NOFLUSH = consts::FOPEN_NOFLUSH;
+        /// Allow concurrent direct writes on the same inode (FOPEN_PARALLEL_DIRECT_WRITES,
+        /// ABI 7.36)
+        const PARALLEL_DIRECT_WRITES = consts::FOPEN_PARALLEL_DIRECT_WRITES;
+        /// Pass reads and writes through to a backing file (FOPEN_PASSTHROUGH, ABI 7.40)
+        const PASSTHROUGH = consts::FOPEN_PASSTHROUGH;
+        /// Purge the attribute cache on open (FOPEN_PURGE_ATTR)
+        #[cfg(target_os = "macos")]
+        const PURGE_ATTR = consts::FOPEN_PURGE_ATTR;
+        /// Purge the unified buffer cache on open (FOPEN_PURGE_UBC)
+        #[cfg(target_os = "macos")]
+        const PURGE_UBC = consts::FOPEN_PURGE_UBC;
+    }
+}
+
+impl OpenOptions {
+    /// Minor protocol version each flag was introduced with
+    const SINCE: &'static [(OpenOptions, u32)] = &[
+        (Self::DIRECT_IO, 0),
+        (Self::KEEP_CACHE, 0),
+        (Self::NONSEEKABLE, 10),
+        (Self::CACHE_DIR, 28),
+        (Self::STREAM, 31),
+        (Self::NOFLUSH, 32),
+        (Self::PARALLEL_DIRECT_WRITES, 36),
+        (Self::PASSTHROUGH, 40),
+        #[cfg(target_os = "macos")]
+        (Self::PURGE_ATTR, 0),
+        #[cfg(target_os = "macos")]
+        (Self::PURGE_UBC, 0),
+    ];
+
+    /// Returns the flags that the given minor version of the FUSE protocol doesn't support,
+    /// including flags unknown to this library
+    pub fn unsupported(self, proto_minor: u32) -> OpenOptions {
+        let supported = Self::SINCE
+            .iter()
+            .filter(|(_, since)| *since <= proto_minor)
+            .fold(0, |bits, (flag, _)| bits | flag.0);
+        OpenOptions(self.0 & !supported)
+    }
+}
+
 /// Filesystem trait.
 ///
 /// This trait must be implemented to provide a userspace filesystem via FUSE.
 /// These methods correspond to fuse_lowlevel_ops in libfuse. Reasonable default
 /// implementations are provided here to get a mountable filesystem that does
 /// nothing.
+///
+/// If the process that triggered an operation receives a signal, the kernel asks to
+/// interrupt it. Methods that may block for a long time should select on
+/// `Request::interrupted()` and reply with `EINTR` when it completes.
+///
+/// The protocol version is negotiated with the kernel on init. Methods for operations that
+/// were added in later versions are only called if the kernel supports them.
 #[async_trait]
+#[allow(clippy::too_many_arguments)]
 pub trait Filesystem {
     /// Initialize filesystem.
-    /// Called before any other filesystem method.
-    async fn init(&self, _req: &Request) -> Result<(), c_int> {
+    /// Called before any other filesystem method. The kernel configuration can be used
+    /// to request capabilities offered by the kernel and to tune connection parameters.
+    async fn init(&self, _req: &Request, _config: &mut KernelConfig) -> Result<(), c_int> {
         Ok(())
     }
 
@@ -115,12 +284,25 @@ pub trait Filesystem {
     /// inodes will receive a forget message.
     async fn forget(&self, _req: &Request, _ino: u64, _nlookup: u64) {}
 
+    /// Forget about multiple inodes at once, given as (ino, nlookup) pairs.
+    /// The default implementation calls `forget` for each of them.
+    async fn batch_forget(&self, req: &Request, nodes: &[(u64, u64)]) {
+        for &(ino, nlookup) in nodes {
+            self.forget(req, ino, nlookup).await;
+        }
+    }
+
     /// Get file attributes.
     async fn getattr(&self, _req: &Request, _ino: u64, reply: ReplyAttr) {
         reply.error(ENOSYS);
     }
 
     /// Set file attributes.
+    /// Only attributes that are `Some` are changed. Times the caller set to the current time
+    /// (e.g. `utimensat` with `UTIME_NOW`) are passed as `TimeOrNow::Now`. If the
+    /// `FUSE_WRITEBACK_CACHE` capability is used, the kernel keeps track of the size, mtime
+    /// and ctime of cached files itself and updates them with setattr, so they need to be
+    /// stored as given instead of being derived from writes.
     async fn setattr(
         &self,
         _req: &Request,
@@ -129,8 +311,9 @@ pub trait Filesystem {
         _uid: Option<u32>,
         _gid: Option<u32>,
         _size: Option<u64>,
-        _atime: Option<SystemTime>,
-        _mtime: Option<SystemTime>,
+        _atime: Option<TimeOrNow>,
+        _mtime: Option<TimeOrNow>,
+        _ctime: Option<SystemTime>,
         _fh: Option<u64>,
         _crtime: Option<SystemTime>,
         _chgtime: Option<SystemTime>,
@@ -142,12 +325,16 @@ pub trait Filesystem {
     }
 
     /// Read symbolic link.
+    /// With the `FUSE_CACHE_SYMLINKS` capability, the kernel caches the link in its page
+    /// cache and calls this only again once the inode is invalidated.
     async fn readlink(&self, _req: &Request, _ino: u64, reply: ReplyData) {
         reply.error(ENOSYS);
     }
 
     /// Create file node.
     /// Create a regular file, character device, block device, fifo or socket node.
+    /// If the `FUSE_SECURITY_CTX` capability is enabled, the security context (e.g. the
+    /// SELinux label) to set on the new node is available in `req.security_context()`.
     async fn mknod(
         &self,
         _req: &Request,
@@ -161,6 +348,7 @@ pub trait Filesystem {
     }
 
     /// Create a directory.
+    /// See `mknod` for the security context of the new directory.
     async fn mkdir(
         &self,
         _req: &Request,
@@ -183,6 +371,7 @@ pub trait Filesystem {
     }
 
     /// Create a symbolic link.
+    /// See `mknod` for the security context of the new link.
     async fn symlink(
         &self,
         _req: &Request,
@@ -195,6 +384,11 @@ pub trait Filesystem {
     }
 
     /// Rename a file.
+    /// Flags are the flags of renameat2 on Linux (ABI 7.23 and later, 0 otherwise):
+    /// RENAME_NOREPLACE fails with EEXIST if the new name already exists, RENAME_EXCHANGE
+    /// atomically exchanges both names (like `exchange` on macOS) and RENAME_WHITEOUT
+    /// leaves a whiteout at the old name (used by overlay filesystems). A filesystem that
+    /// doesn't support a given flag should reply with EINVAL.
     async fn rename(
         &self,
         _req: &Request,
@@ -202,6 +396,7 @@ pub trait Filesystem {
         _name: &OsStr,
         _newparent: u64,
         _newname: &OsStr,
+        _flags: u32,
         reply: ReplyEmpty,
     ) {
         reply.error(ENOSYS);
@@ -226,9 +421,13 @@ pub trait Filesystem {
     /// release, fsync). Filesystem may also implement stateless file I/O and not store
     /// anything in fh. There are also some flags (direct_io, keep_cache) which the
     /// filesystem may set, to change the way the file is opened. See fuse_file_info
-    /// structure in <fuse_common.h> for more details.
+    /// structure in <fuse_common.h> for more details. With the `FUSE_PASSTHROUGH`
+    /// capability, the kernel can serve reads and writes from a backing file instead (see
+    /// `Request::open_backing`). With the `FUSE_NO_OPEN_SUPPORT` capability, replying
+    /// ENOSYS lets this and all further opens succeed without calling this method again
+    /// (fh is 0 then).
     async fn open(&self, _req: &Request, _ino: u64, _flags: u32, reply: ReplyOpen) {
-        reply.opened(0, 0);
+        reply.opened(0, OpenOptions::empty());
     }
 
     /// Read data.
@@ -256,6 +455,12 @@ pub trait Filesystem {
     /// which case the return value of the write system call will reflect the return
     /// value of this operation. fh will contain the value set by the open method, or
     /// will be undefined if the open method didn't set any value.
+    ///
+    /// With the `FUSE_WRITEBACK_CACHE` capability, the kernel buffers writes in the page
+    /// cache and sends them later. Such delayed writes have `FUSE_WRITE_CACHE` set in flags
+    /// and fh is guessed: it can be any handle opened for writing on the inode, possibly of
+    /// another process. The kernel also reads from files opened write-only to fill the cache
+    /// and handles `O_APPEND` by itself, so write offsets are always explicit.
     async fn write(
         &self,
         _req: &Request,
@@ -324,9 +529,11 @@ pub trait Filesystem {
     /// fsyncdir). Filesystem may also implement stateless directory I/O and not store
     /// anything in fh, though that makes it impossible to implement standard conforming
     /// directory stream operations in case the contents of the directory can change
-    /// between opendir and releasedir.
+    /// between opendir and releasedir. With the `FUSE_NO_OPENDIR_SUPPORT` capability,
+    /// replying ENOSYS lets this and all further opendirs succeed without calling this
+    /// method again (fh is 0 then).
     async fn opendir(&self, _req: &Request, _ino: u64, _flags: u32, reply: ReplyOpen) {
-        reply.opened(0, 0);
+        reply.opened(0, OpenOptions::empty());
     }
 
     /// Read directory.
@@ -345,6 +552,23 @@ pub trait Filesystem {
         reply.error(ENOSYS);
     }
 
+    /// Read directory with attributes of the entries.
+    /// Only called if the FUSE_DO_READDIRPLUS or FUSE_READDIRPLUS_AUTO capability was
+    /// requested during init. Works like readdir, but every entry is added with its
+    /// attributes, which saves a lookup for each of them. Since every added entry (except
+    /// "." and "..") counts as a lookup, the filesystem needs to increase the lookup count
+    /// of the entries' inodes like it does in lookup.
+    async fn readdirplus(
+        &self,
+        _req: &Request,
+        _ino: u64,
+        _fh: u64,
+        _offset: i64,
+        reply: ReplyDirectoryPlus,
+    ) {
+        reply.error(ENOSYS);
+    }
+
     /// Release an open directory.
     /// For every opendir call there will be exactly one releasedir call. fh will
     /// contain the value set by the opendir method, or will be undefined if the
@@ -381,6 +605,12 @@ pub trait Filesystem {
     }
 
     /// Set an extended attribute.
+    /// Flags are XATTR_CREATE or XATTR_REPLACE. Setxattr flags are `FUSE_SETXATTR_*` flags
+    /// if the `FUSE_SETXATTR_EXT` capability is enabled (Linux with ABI 7.33 and later,
+    /// requested by default), 0 otherwise: FUSE_SETXATTR_ACL_KILL_SGID asks to clear
+    /// the SGID bit of the file while setting its access ACL. With the `FUSE_POSIX_ACL`
+    /// capability, the kernel checks permissions using the ACLs stored in the
+    /// `system.posix_acl_access` and `system.posix_acl_default` attributes (see `PosixAcl`).
     async fn setxattr(
         &self,
         _req: &Request,
@@ -388,6 +618,7 @@ pub trait Filesystem {
         _name: &OsStr,
         _value: &[u8],
         _flags: u32,
+        _setxattr_flags: u32,
         _position: u32,
         reply: ReplyEmpty,
     ) {
@@ -439,7 +670,8 @@ pub trait Filesystem {
     /// filesystem may set, to change the way the file is opened. See fuse_file_info
     /// structure in <fuse_common.h> for more details. If this method is not
     /// implemented or under Linux kernel versions earlier than 2.6.15, the mknod()
-    /// and open() methods will be called instead.
+    /// and open() methods will be called instead. See `mknod` for the security context
+    /// of the new file.
     async fn create(
         &self,
         _req: &Request,
@@ -498,6 +730,139 @@ pub trait Filesystem {
         reply.error(ENOSYS);
     }
 
+    /// Control device.
+    /// The ioctl argument is given as `arg` (an address in the calling process) and, for
+    /// restricted ioctls, the input data the kernel copied from it. The reply may contain up
+    /// to `out_size` bytes of output data. Unrestricted ioctls (FUSE_IOCTL_UNRESTRICTED in
+    /// flags) can be retried with memory areas of the caller, see `ReplyIoctl::retry`.
+    /// Ioctls of 32-bit processes on a 64-bit kernel have FUSE_IOCTL_COMPAT set and may use
+    /// different command numbers and argument layouts.
+    async fn ioctl(
+        &self,
+        _req: &Request,
+        _ino: u64,
+        _fh: u64,
+        _flags: u32,
+        _cmd: u32,
+        _arg: u64,
+        _in_data: &[u8],
+        _out_size: u32,
+        reply: ReplyIoctl,
+    ) {
+        reply.error(ENOSYS);
+    }
+
+    /// Preallocate or deallocate space of a file.
+    /// Filesystems should reply with EOPNOTSUPP for mode flags they don't support.
+    async fn fallocate(
+        &self,
+        _req: &Request,
+        _ino: u64,
+        _fh: u64,
+        _offset: u64,
+        _length: u64,
+        _mode: FallocateFlags,
+        reply: ReplyEmpty,
+    ) {
+        reply.error(ENOSYS);
+    }
+
+    /// Reposition the offset of an open file.
+    /// Only called for SEEK_DATA and SEEK_HOLE (in whence), which find the next data or hole
+    /// at or after the given offset in sparse files. Other seeks are handled by the kernel.
+    /// If this method is not implemented (ENOSYS), the kernel remembers it and falls back to
+    /// generic seeking, which treats the whole file as data, for all further calls.
+    async fn lseek(
+        &self,
+        _req: &Request,
+        _ino: u64,
+        _fh: u64,
+        _offset: i64,
+        _whence: i32,
+        reply: ReplyLseek,
+    ) {
+        reply.error(ENOSYS);
+    }
+
+    /// Copy a range of data from one file to another.
+    /// Lets the filesystem copy data without sending it through the kernel and back (e.g.
+    /// by a server-side copy). The number of bytes copied is replied, which may be less
+    /// than the requested length. If this method is not implemented (ENOSYS), the kernel
+    /// remembers it and falls back to copying by read and write for all further calls.
+    async fn copy_file_range(
+        &self,
+        _req: &Request,
+        _ino_in: u64,
+        _fh_in: u64,
+        _offset_in: i64,
+        _ino_out: u64,
+        _fh_out: u64,
+        _offset_out: i64,
+        _len: u64,
+        _flags: u64,
+        reply: ReplyWrite,
+    ) {
+        reply.error(ENOSYS);
+    }
+
+    /// Synchronize the whole filesystem.
+    /// Called on syncfs(2) (ABI 7.34 and later) to write back all data of the filesystem,
+    /// e.g. flushing it to a backend. The inode is the root of the filesystem. If this method
+    /// is not implemented (ENOSYS), the kernel remembers it and doesn't call it again.
+    async fn syncfs(&self, _req: &Request, _ino: u64, reply: ReplyEmpty) {
+        reply.error(ENOSYS);
+    }
+
+    /// Create an unnamed temporary file.
+    /// Called on open(2) with O_TMPFILE (ABI 7.37 and later). Works like `create`, but the
+    /// file isn't linked into the parent directory. It can be linked later with `link`, or
+    /// is removed once released and forgotten. If this method is not implemented (ENOSYS),
+    /// the kernel remembers it and fails all further O_TMPFILE opens with EOPNOTSUPP.
+    async fn tmpfile(
+        &self,
+        _req: &Request,
+        _parent: u64,
+        _mode: u32,
+        _flags: u32,
+        reply: ReplyCreate,
+    ) {
+        reply.error(ENOSYS);
+    }
+
+    /// Get extended file attributes (statx).
+    /// Called on statx(2) (ABI 7.39 and later) to additionally get the creation time and
+    /// `STATX_ATTR_*` attributes. fh is given if the request is for an open file. The flags
+    /// are `AT_STATX_*` sync flags and mask tells which `STATX_*` fields are requested. If this
+    /// method is not implemented (ENOSYS), the kernel remembers it and calls `getattr` for all
+    /// further requests.
+    async fn statx(
+        &self,
+        _req: &Request,
+        _ino: u64,
+        _fh: Option<u64>,
+        _flags: u32,
+        _mask: u32,
+        reply: ReplyStatx,
+    ) {
+        reply.error(ENOSYS);
+    }
+
+    /// Poll for IO readiness events.
+    /// If the kernel wants to be notified about readiness changes (FUSE_POLL_SCHEDULE_NOTIFY
+    /// in flags), a poll handle is given. It should be kept and used to notify the kernel
+    /// once the file becomes ready, even after replying to this request.
+    async fn poll(
+        &self,
+        _req: &Request,
+        _ino: u64,
+        _fh: u64,
+        _ph: Option<PollHandle>,
+        _flags: u32,
+        reply: ReplyPoll,
+    ) {
+        reply.error(ENOSYS);
+    }
+
     /// macOS only: Rename the volume. Set fuse_init_out.flags during init to
     /// FUSE_VOL_RENAME to enable
     #[cfg(target_os = "macos")]
@@ -528,25 +893,30 @@ pub trait Filesystem {
     }
 }
 
-/// Mount the given filesystem to the given mountpoint. This function will
-/// not return until the filesystem is unmounted.
+/// Mount the given filesystem to the given mountpoint. The returned future
+/// will not complete until the filesystem is unmounted.
 ///
 /// Note that you need to lead each option with a separate `"-o"` string. See
 /// `examples/hello.rs`.
-pub fn mount<FS: Filesystem + Send + Sync + 'static, P: AsRef<Path>>(
+pub async fn mount<FS: Filesystem + Send + Sync + 'static, P: AsRef<Path>>(
     filesystem: FS,
     mountpoint: P,
     options: &[OsString],
 ) -> io::Result<()> {
     let se = Session::new(filesystem, mountpoint.as_ref(), options)?;
-    se.run()
+    se.run().await
 }
 
 /// Mount the given filesystem to the given mountpoint. This function spawns
-/// a background thread to handle filesystem operations while being mounted
+/// a background task to handle filesystem operations while being mounted
 /// and therefore returns immediately. The returned handle should be stored
 /// to reference the mounted filesystem. If it's dropped, the filesystem will
 /// be unmounted.
+///
+/// # Safety
+///
+/// The session loop is spawned onto the current tokio runtime, so this must be called from
+/// within a runtime context.
 pub unsafe fn spawn_mount<FS: Filesystem + Send + Sync + 'static, P: AsRef<Path>>(
     filesystem: FS,
     mountpoint: P,
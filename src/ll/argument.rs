@@ -6,6 +6,7 @@
 use std::ffi::OsStr;
 use std::mem;
 use std::os::unix::ffi::OsStrExt;
+use std::ptr;
 
 /// An iterator that can be used to fetch typed arguments from a byte slice.
 pub struct ArgumentIterator<'a> {
@@ -48,6 +49,18 @@ impl<'a> ArgumentIterator<'a> {
         (bytes.as_ptr() as *const T).as_ref()
     }
 
+    /// Fetch a typed argument of which only the first `len` bytes are present, like older and
+    /// shorter versions of a struct. The missing fields are zeroed. Returns `None` if there's
+    /// not enough data left. This function is unsafe because there is no guarantee that the
+    /// data actually contains the type T.
+    pub unsafe fn fetch_compat<T: Copy>(&mut self, len: usize) -> Option<T> {
+        let len = len.min(mem::size_of::<T>());
+        let bytes = self.fetch_bytes(len)?;
+        let mut arg = mem::MaybeUninit::<T>::zeroed();
+        ptr::copy_nonoverlapping(bytes.as_ptr(), arg.as_mut_ptr() as *mut u8, len);
+        Some(arg.assume_init())
+    }
+
     /// Fetch a (zero-terminated) string (can be non-utf8). Returns `None` if there's not enough
     /// data left or no zero-termination could be found. This function is unsafe because there is
     /// no guarantee that the data actually contains a string.
@@ -55,7 +68,7 @@ impl<'a> ArgumentIterator<'a> {
         let len = self.data.iter().position(|&c| c == 0)?;
         let bytes = self.fetch_bytes(len)?;
         let _zero = self.fetch_bytes(1)?;
-        Some(OsStr::from_bytes(&bytes))
+        Some(OsStr::from_bytes(bytes))
     }
 }
 
@@ -66,6 +79,7 @@ mod tests {
     const TEST_DATA: [u8; 10] = [0x66, 0x6f, 0x6f, 0x00, 0x62, 0x61, 0x72, 0x00, 0x62, 0x61];
 
     #[repr(C)]
+    #[derive(Clone, Copy)]
     struct TestArgument {
         p1: u8,
         p2: u8,
@@ -138,4 +152,20 @@ mod tests {
         assert!(arg.is_none());
         assert_eq!(it.len(), 2);
     }
+
+    #[test]
+    fn compat_argument() {
+        let mut it = ArgumentIterator::new(&TEST_DATA);
+        let arg: TestArgument = unsafe { it.fetch_compat(2).unwrap() };
+        assert_eq!(arg.p1, 0x66);
+        assert_eq!(arg.p2, 0x6f);
+        assert_eq!(arg.p3, 0);
+        assert_eq!(it.len(), 8);
+        let arg: TestArgument = unsafe { it.fetch_compat(8).unwrap() };
+        assert_eq!(arg.p1, 0x6f);
+        assert_eq!(arg.p2, 0x00);
+        assert_eq!(it.len(), 4);
+        it.fetch_bytes(2).unwrap();
+        assert!(unsafe { it.fetch_compat::<TestArgument>(4) }.is_none());
+    }
 }
@@ -3,6 +3,7 @@
 //! A request represents information about a filesystem operation the kernel driver wants us to
 //! perform.
 
+use fuse_abi::consts::FUSE_MAX_NR_SECCTX;
 use fuse_abi::*;
 use std::convert::TryFrom;
 use std::ffi::OsString;
@@ -158,29 +159,45 @@ pub enum Operation {
         arg: fuse_bmap_in,
     },
     Destroy,
-    // TODO: FUSE_IOCTL since ABI 7.11
-    #[cfg(feature = "abi-7-11")]
     IoCtl {
         arg: fuse_ioctl_in,
         data: Vec<u8>,
     },
-    #[cfg(feature = "abi-7-11")]
     Poll {
         arg: fuse_poll_in,
     },
-    #[cfg(feature = "abi-7-15")]
     NotifyReply {
+        arg: fuse_notify_retrieve_in,
         data: Vec<u8>,
     },
-    #[cfg(feature = "abi-7-16")]
     BatchForget {
-        arg: fuse_forget_in,
+        arg: fuse_batch_forget_in,
         nodes: Vec<fuse_forget_one>,
     },
-    #[cfg(feature = "abi-7-19")]
     FAllocate {
         arg: fuse_fallocate_in,
     },
+    ReadDirPlus {
+        arg: fuse_read_in,
+    },
+    Rename2 {
+        arg: fuse_rename2_in,
+        name: OsString,
+        newname: OsString,
+    },
+    Lseek {
+        arg: fuse_lseek_in,
+    },
+    CopyFileRange {
+        arg: fuse_copy_file_range_in,
+    },
+    SyncFs,
+    TmpFile {
+        arg: fuse_create_in,
+    },
+    Statx {
+        arg: fuse_statx_in,
+    },
     #[cfg(target_os = "macos")]
     SetVolName {
         name: OsString,
@@ -193,13 +210,12 @@ pub enum Operation {
         oldname: OsString,
         newname: OsString,
     },
-    #[cfg(feature = "abi-7-12")]
     CuseInit {
-        arg: fuse_init_in,
+        arg: cuse_init_in,
     },
 }
 
-impl<'a> fmt::Display for Operation {
+impl fmt::Display for Operation {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Operation::Lookup { name } => write!(f, "LOOKUP name {:?}", name),
@@ -238,18 +254,19 @@ impl<'a> fmt::Display for Operation {
             Operation::Interrupt { arg } => write!(f, "INTERRUPT unique {}", arg.unique),
             Operation::BMap { arg } => write!(f, "BMAP blocksize {}, ids {}", arg.blocksize, arg.block),
             Operation::Destroy => write!(f, "DESTROY"),
-            #[cfg(feature = "abi-7-11")]
-            Operation::IoCtl { arg, .. } => write!(f, "IOCTL fh {}", arg.fh),
-            #[cfg(feature = "abi-7-11")]
-            Operation::Poll {arg } => write!(f, "GETLK fh {}", arg.fh),
-            #[cfg(feature = "abi-7-15")]
-            Operation::NotifyReply{..}  => write!(f, "NOTIFY_REPLY"),
-            #[cfg(feature = "abi-7-16")]
-             Operation::BatchForget {..} => write!(f, "BATCH_FORGET fh "),
-            #[cfg(feature = "abi-7-19")]
-            Operation::FAllocate { .. }=> write!(f, "FALLOCATE fh"),
-            #[cfg(feature = "abi-7-12")]
-            Operation::CuseInit {..} => write!(f, "CUSEINIT fh"),
+            Operation::IoCtl { arg, .. } => write!(f, "IOCTL fh {}, flags {:#x}, cmd {:#x}", arg.fh, arg.flags, arg.cmd),
+            Operation::Poll {arg } => write!(f, "POLL fh {}, flags {:#x}", arg.fh, arg.flags),
+            Operation::NotifyReply { arg, data } => write!(f, "NOTIFY_REPLY offset {}, size {}", arg.offset, data.len()),
+             Operation::BatchForget { arg, .. } => write!(f, "BATCH_FORGET count {}", arg.count),
+            Operation::FAllocate { arg } => write!(f, "FALLOCATE fh {}, offset {}, length {}, mode {:#x}", arg.fh, arg.offset, arg.length, arg.mode),
+            Operation::ReadDirPlus { arg } => write!(f, "READDIRPLUS fh {}, offset {}, size {}", arg.fh, arg.offset, arg.size),
+            Operation::Rename2 { arg, name, newname } => write!(f, "RENAME2 name {:?}, newdir {:#018x}, newname {:?}, flags {:#x}", name, arg.newdir, newname, arg.flags),
+            Operation::Lseek { arg } => write!(f, "LSEEK fh {}, offset {}, whence {}", arg.fh, arg.offset, arg.whence),
+            Operation::CopyFileRange { arg } => write!(f, "COPY_FILE_RANGE fh_in {}, off_in {}, nodeid_out {:#018x}, fh_out {}, off_out {}, len {}, flags {:#x}", arg.fh_in, arg.off_in, arg.nodeid_out, arg.fh_out, arg.off_out, arg.len, arg.flags),
+            Operation::SyncFs => write!(f, "SYNCFS"),
+            Operation::TmpFile { arg } => write!(f, "TMPFILE mode {:#05o}, flags {:#x}", arg.mode, arg.flags),
+            Operation::Statx { arg } => write!(f, "STATX getattr_flags {:#x}, fh {}, flags {:#x}, mask {:#x}", arg.getattr_flags, arg.fh, arg.sx_flags, arg.sx_mask),
+            Operation::CuseInit { arg } => write!(f, "CUSE_INIT kernel ABI {}.{}, flags {:#x}", arg.major, arg.minor, arg.flags),
             #[cfg(target_os = "macos")]
             Operation::SetVolName { name } => write!(f, "SETVOLNAME name {:?}", name),
             #[cfg(target_os = "macos")]
@@ -260,8 +277,56 @@ impl<'a> fmt::Display for Operation {
     }
 }
 
+/// Fetch the argument of an init request. Kernels before ABI 7.36 send a shorter argument
+/// without flags2, which is zero-extended.
+unsafe fn fetch_init_in(data: &mut ArgumentIterator<'_>) -> Option<fuse_init_in> {
+    if data.len() < FUSE_COMPAT_INIT_IN_SIZE {
+        return None;
+    }
+    data.fetch_compat(data.len())
+}
+
+/// Fetch an argument that was extended in ABI 7.`since`. Kernels using an older protocol
+/// version send the compat layout of the given size, which is zero-extended.
+unsafe fn fetch_versioned<'a, T: Copy + 'a>(
+    data: &mut ArgumentIterator<'a>,
+    proto_minor: u32,
+    since: u32,
+    compat_size: usize,
+) -> Option<T> {
+    if proto_minor < since {
+        data.fetch_compat(compat_size)
+    } else {
+        data.fetch().copied()
+    }
+}
+
+/// Minor protocol version the given operation was introduced with. Older operations are
+/// always accepted, even if the kernel shouldn't send them yet.
+fn since(opcode: &fuse_opcode) -> u32 {
+    match opcode {
+        fuse_opcode::FUSE_SYNCFS => 34,
+        fuse_opcode::FUSE_TMPFILE => 37,
+        fuse_opcode::FUSE_STATX => 39,
+        _ => 0,
+    }
+}
+
+/// Fetch all remaining data, which must be of the given size
+fn fetch_sized(data: &mut ArgumentIterator<'_>, size: u32) -> Option<Vec<u8>> {
+    if data.len() != size as usize {
+        return None;
+    }
+    Some(data.fetch_all().to_vec())
+}
+
 impl Operation {
-    fn parse(opcode: &fuse_opcode, data: &mut ArgumentIterator<'_>) -> Option<Self> {
+    fn parse(
+        opcode: &fuse_opcode,
+        data: &mut ArgumentIterator<'_>,
+        proto_minor: u32,
+        setxattr_ext: bool,
+    ) -> Option<Self> {
         unsafe {
             Some(match opcode {
                 fuse_opcode::FUSE_LOOKUP => Operation::Lookup {
@@ -280,7 +345,7 @@ impl Operation {
                     link: data.fetch_str()?.into(),
                 },
                 fuse_opcode::FUSE_MKNOD => Operation::MkNod {
-                    arg: *data.fetch()?,
+                    arg: fetch_versioned(data, proto_minor, 12, FUSE_COMPAT_MKNOD_IN_SIZE)?,
                     name: data.fetch_str()?.into(),
                 },
                 fuse_opcode::FUSE_MKDIR => Operation::MkDir {
@@ -306,12 +371,14 @@ impl Operation {
                     arg: *data.fetch()?,
                 },
                 fuse_opcode::FUSE_READ => Operation::Read {
-                    arg: *data.fetch()?,
-                },
-                fuse_opcode::FUSE_WRITE => Operation::Write {
-                    arg: *data.fetch()?,
-                    data: data.fetch_all().to_vec(),
+                    arg: fetch_versioned(data, proto_minor, 9, FUSE_COMPAT_READ_IN_SIZE)?,
                 },
+                fuse_opcode::FUSE_WRITE => {
+                    let arg: fuse_write_in =
+                        fetch_versioned(data, proto_minor, 9, FUSE_COMPAT_WRITE_IN_SIZE)?;
+                    let data = fetch_sized(data, arg.size)?;
+                    Operation::Write { arg, data }
+                }
                 fuse_opcode::FUSE_STATFS => Operation::StatFs,
                 fuse_opcode::FUSE_RELEASE => Operation::Release {
                     arg: *data.fetch()?,
@@ -319,11 +386,22 @@ impl Operation {
                 fuse_opcode::FUSE_FSYNC => Operation::FSync {
                     arg: *data.fetch()?,
                 },
-                fuse_opcode::FUSE_SETXATTR => Operation::SetXAttr {
-                    arg: *data.fetch()?,
-                    name: data.fetch_str()?.into(),
-                    value: data.fetch_all().to_vec(),
-                },
+                fuse_opcode::FUSE_SETXATTR => {
+                    // The extended argument is only sent if FUSE_SETXATTR_EXT is enabled
+                    #[cfg(not(target_os = "macos"))]
+                    let arg: fuse_setxattr_in = match setxattr_ext {
+                        true => *data.fetch()?,
+                        false => data.fetch_compat(FUSE_COMPAT_SETXATTR_IN_SIZE)?,
+                    };
+                    #[cfg(target_os = "macos")]
+                    let arg: fuse_setxattr_in = {
+                        let _ = setxattr_ext;
+                        *data.fetch()?
+                    };
+                    let name = data.fetch_str()?.into();
+                    let value = fetch_sized(data, arg.size)?;
+                    Operation::SetXAttr { arg, name, value }
+                }
                 fuse_opcode::FUSE_GETXATTR => Operation::GetXAttr {
                     arg: *data.fetch()?,
                     name: data.fetch_str()?.into(),
@@ -338,13 +416,13 @@ impl Operation {
                     arg: *data.fetch()?,
                 },
                 fuse_opcode::FUSE_INIT => Operation::Init {
-                    arg: *data.fetch()?,
+                    arg: fetch_init_in(data)?,
                 },
                 fuse_opcode::FUSE_OPENDIR => Operation::OpenDir {
                     arg: *data.fetch()?,
                 },
                 fuse_opcode::FUSE_READDIR => Operation::ReadDir {
-                    arg: *data.fetch()?,
+                    arg: fetch_versioned(data, proto_minor, 9, FUSE_COMPAT_READ_IN_SIZE)?,
                 },
                 fuse_opcode::FUSE_RELEASEDIR => Operation::ReleaseDir {
                     arg: *data.fetch()?,
@@ -353,19 +431,19 @@ impl Operation {
                     arg: *data.fetch()?,
                 },
                 fuse_opcode::FUSE_GETLK => Operation::GetLk {
-                    arg: *data.fetch()?,
+                    arg: fetch_versioned(data, proto_minor, 9, FUSE_COMPAT_LK_IN_SIZE)?,
                 },
                 fuse_opcode::FUSE_SETLK => Operation::SetLk {
-                    arg: *data.fetch()?,
+                    arg: fetch_versioned(data, proto_minor, 9, FUSE_COMPAT_LK_IN_SIZE)?,
                 },
                 fuse_opcode::FUSE_SETLKW => Operation::SetLkW {
-                    arg: *data.fetch()?,
+                    arg: fetch_versioned(data, proto_minor, 9, FUSE_COMPAT_LK_IN_SIZE)?,
                 },
                 fuse_opcode::FUSE_ACCESS => Operation::Access {
                     arg: *data.fetch()?,
                 },
                 fuse_opcode::FUSE_CREATE => Operation::Create {
-                    arg: *data.fetch()?,
+                    arg: fetch_versioned(data, proto_minor, 12, FUSE_COMPAT_CREATE_IN_SIZE)?,
                     name: data.fetch_str()?.into(),
                 },
                 fuse_opcode::FUSE_INTERRUPT => Operation::Interrupt {
@@ -376,36 +454,56 @@ impl Operation {
                 },
                 fuse_opcode::FUSE_DESTROY => Operation::Destroy,
 
-                #[cfg(feature = "abi-7-11")]
                 fuse_opcode::FUSE_IOCTL => Operation::IoCtl {
                     arg: *data.fetch()?,
                     data: data.fetch_all().to_vec(),
                 },
-                #[cfg(feature = "abi-7-11")]
                 fuse_opcode::FUSE_POLL => Operation::Poll {
                     arg: *data.fetch()?,
                 },
-                #[cfg(feature = "abi-7-15")]
                 fuse_opcode::FUSE_NOTIFY_REPLY => Operation::NotifyReply {
+                    arg: *data.fetch()?,
                     data: data.fetch_all().to_vec(),
                 },
-                #[cfg(feature = "abi-7-16")]
                 fuse_opcode::FUSE_BATCH_FORGET => {
-                    let arg = *data.fetch()?;
-                    let mut nodes: Vec<fuse_forget_one> = Vec::new();
-                    while let Some(node) = data.fetch::<fuse_forget_one>() {
-                        nodes.push(node.clone());
-                    }
-                    Operation::BatchForget {
-                        arg: arg,
-                        nodes: nodes,
+                    let arg: fuse_batch_forget_in = *data.fetch()?;
+                    // Don't trust the count for allocating before the nodes were fetched
+                    let count =
+                        (arg.count as usize).min(data.len() / mem::size_of::<fuse_forget_one>());
+                    let mut nodes: Vec<fuse_forget_one> = Vec::with_capacity(count);
+                    for _ in 0..arg.count {
+                        nodes.push(*data.fetch()?);
                     }
+                    Operation::BatchForget { arg, nodes }
                 }
-                #[cfg(feature = "abi-7-19")]
                 fuse_opcode::FUSE_FALLOCATE => Operation::FAllocate {
                     arg: *data.fetch()?,
                 },
-                #[cfg(feature = "abi-7-12")]
+                fuse_opcode::FUSE_READDIRPLUS => Operation::ReadDirPlus {
+                    arg: *data.fetch()?,
+                },
+                fuse_opcode::FUSE_RENAME2 => Operation::Rename2 {
+                    arg: *data.fetch()?,
+                    name: data.fetch_str()?.into(),
+                    newname: data.fetch_str()?.into(),
+                },
+                fuse_opcode::FUSE_LSEEK => Operation::Lseek {
+                    arg: *data.fetch()?,
+                },
+                fuse_opcode::FUSE_COPY_FILE_RANGE => Operation::CopyFileRange {
+                    arg: *data.fetch()?,
+                },
+                fuse_opcode::FUSE_SYNCFS => {
+                    let _arg: &fuse_syncfs_in = data.fetch()?;
+                    Operation::SyncFs
+                }
+                // The name of a tmpfile is just a placeholder
+                fuse_opcode::FUSE_TMPFILE => Operation::TmpFile {
+                    arg: *data.fetch()?,
+                },
+                fuse_opcode::FUSE_STATX => Operation::Statx {
+                    arg: *data.fetch()?,
+                },
                 fuse_opcode::CUSE_INIT => Operation::CuseInit {
                     arg: *data.fetch()?,
                 },
@@ -427,11 +525,39 @@ impl Operation {
     }
 }
 
+/// Fetch the security context the kernel appends to requests creating an inode if
+/// FUSE_SECURITY_CTX is enabled. Returns (name, value) pairs, like the xattr name and value of a
+/// security label. Other request extensions are skipped.
+unsafe fn fetch_secctx(data: &mut ArgumentIterator<'_>) -> Option<Vec<(OsString, Vec<u8>)>> {
+    let mut secctx = Vec::new();
+    while data.len() > 0 {
+        // Extension headers are compatible with the security context header
+        let header: fuse_secctx_header = data.fetch_compat(mem::size_of::<fuse_secctx_header>())?;
+        let size = (header.size as usize).checked_sub(mem::size_of::<fuse_secctx_header>())?;
+        let mut ext = ArgumentIterator::new(data.fetch_bytes(size)?);
+        if header.nr_secctx > FUSE_MAX_NR_SECCTX {
+            continue;
+        }
+        for _ in 0..header.nr_secctx {
+            let ctx: fuse_secctx = ext.fetch_compat(mem::size_of::<fuse_secctx>())?;
+            let len = ext.len();
+            let name = ext.fetch_str()?;
+            let value = ext.fetch_bytes(ctx.size as usize)?;
+            // Every record is aligned to 8 bytes
+            let rec_len = mem::size_of::<fuse_secctx>() + len - ext.len();
+            ext.fetch_bytes(((rec_len + 7) & !7) - rec_len)?;
+            secctx.push((name.into(), value.to_vec()));
+        }
+    }
+    Some(secctx)
+}
+
 /// Low-level request of a filesystem operation the kernel driver wants to perform.
 #[derive(Debug)]
 pub struct Request {
     header: fuse_in_header,
     operation: Operation,
+    secctx: Vec<(OsString, Vec<u8>)>,
 }
 
 impl fmt::Display for Request {
@@ -448,6 +574,15 @@ impl TryFrom<&[u8]> for Request {
     type Error = RequestError;
 
     fn try_from(data: &[u8]) -> Result<Self, Self::Error> {
+        Self::parse(data, FUSE_KERNEL_MINOR_VERSION, true)
+    }
+}
+
+impl Request {
+    /// Parse a raw packet as sent by a kernel driver that uses the given minor version of the
+    /// FUSE protocol. Arguments of older protocol versions are zero-extended to the current
+    /// layout, like setxattr arguments if FUSE_SETXATTR_EXT wasn't negotiated.
+    pub fn parse(data: &[u8], proto_minor: u32, setxattr_ext: bool) -> Result<Self, RequestError> {
         // Parse a raw packet as sent by the kernel driver into typed data. Every request always
         // begins with a `fuse_in_header` struct followed by arguments depending on the opcode.
         let data_len = data.len();
@@ -455,23 +590,48 @@ impl TryFrom<&[u8]> for Request {
         // Parse header
         let header: &fuse_in_header =
             unsafe { data.fetch() }.ok_or_else(|| RequestError::ShortReadHeader(data.len()))?;
-        // Parse/check opcode
+        // Parse/check opcode. Operations of a newer protocol version than the negotiated one
+        // are unknown.
         let opcode = fuse_opcode::try_from(header.opcode)
-            .map_err(|_: InvalidOpcodeError| RequestError::UnknownOperation(header.opcode))?;
+            .ok()
+            .filter(|opcode| since(opcode) <= proto_minor)
+            .ok_or(RequestError::UnknownOperation(header.opcode))?;
         // Check data size
         if data_len < header.len as usize {
             return Err(RequestError::ShortRead(data_len, header.len as usize));
         }
+        // Only parse the arguments of this request, ignoring any trailing data
+        let args_len = (header.len as usize)
+            .checked_sub(mem::size_of::<fuse_in_header>())
+            .ok_or(RequestError::InsufficientData)?;
+        let args = data
+            .fetch_bytes(args_len)
+            .ok_or(RequestError::InsufficientData)?;
+        let mut data = ArgumentIterator::new(args);
         // Parse/check operation arguments
-        let operation =
-            Operation::parse(&opcode, &mut data).ok_or_else(|| RequestError::InsufficientData)?;
+        let operation = Operation::parse(&opcode, &mut data, proto_minor, setxattr_ext)
+            .ok_or(RequestError::InsufficientData)?;
+        // Requests creating an inode may be followed by a security context (ABI 7.36 and later)
+        let secctx = match operation {
+            Operation::MkNod { .. }
+            | Operation::MkDir { .. }
+            | Operation::SymLink { .. }
+            | Operation::Create { .. }
+                if proto_minor >= 36 =>
+            {
+                unsafe { fetch_secctx(&mut data) }.ok_or(RequestError::InsufficientData)?
+            }
+            _ => Vec::new(),
+        };
         let header = *header;
 
-        Ok(Self { header, operation })
+        Ok(Self {
+            header,
+            operation,
+            secctx,
+        })
     }
-}
 
-impl Request {
     /// Returns the unique identifier of this request.
     ///
     /// The FUSE kernel driver assigns a unique id to every concurrent request. This allows to
@@ -511,6 +671,13 @@ impl Request {
     pub fn operation(&self) -> &Operation {
         &self.operation
     }
+
+    /// Returns the security context (name and value pairs) of the inode to create. Only
+    /// requests creating an inode have one, if FUSE_SECURITY_CTX is enabled.
+    #[inline]
+    pub fn security_context(&self) -> &[(OsString, Vec<u8>)] {
+        &self.secctx
+    }
 }
 
 #[cfg(test)]
@@ -540,7 +707,7 @@ mod tests {
     ];
 
     #[cfg(target_endian = "big")]
-    const MKNOD_REQUEST: [u8; 56] = [
+    const MKNOD_COMPAT_REQUEST: [u8; 56] = [
         0x00, 0x00, 0x00, 0x38, 0x00, 0x00, 0x00, 0x08, // len, opcode
         0xde, 0xad, 0xbe, 0xef, 0xba, 0xad, 0xd0, 0x0d, // unique
         0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, // nodeid
@@ -551,7 +718,7 @@ mod tests {
     ];
 
     #[cfg(target_endian = "little")]
-    const MKNOD_REQUEST: [u8; 56] = [
+    const MKNOD_COMPAT_REQUEST: [u8; 56] = [
         0x38, 0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00, // len, opcode
         0x0d, 0xf0, 0xad, 0xba, 0xef, 0xbe, 0xad, 0xde, // unique
         0x88, 0x77, 0x66, 0x55, 0x44, 0x33, 0x22, 0x11, // nodeid
@@ -561,6 +728,82 @@ mod tests {
         0x66, 0x6f, 0x6f, 0x2e, 0x74, 0x78, 0x74, 0x00, // name
     ];
 
+    #[cfg(target_endian = "big")]
+    const MKNOD_REQUEST: [u8; 64] = [
+        0x00, 0x00, 0x00, 0x40, 0x00, 0x00, 0x00, 0x08, // len, opcode
+        0xde, 0xad, 0xbe, 0xef, 0xba, 0xad, 0xd0, 0x0d, // unique
+        0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, // nodeid
+        0xc0, 0x01, 0xd0, 0x0d, 0xc0, 0x01, 0xca, 0xfe, // uid, gid
+        0xc0, 0xde, 0xba, 0x5e, 0x00, 0x00, 0x00, 0x00, // pid, padding
+        0x00, 0x00, 0x01, 0xa4, 0x00, 0x00, 0x00, 0x00, // mode, rdev
+        0x00, 0x00, 0x00, 0x12, 0x00, 0x00, 0x00, 0x00, // umask, padding
+        0x66, 0x6f, 0x6f, 0x2e, 0x74, 0x78, 0x74, 0x00, // name
+    ];
+
+    #[cfg(target_endian = "little")]
+    const MKNOD_REQUEST: [u8; 64] = [
+        0x40, 0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00, // len, opcode
+        0x0d, 0xf0, 0xad, 0xba, 0xef, 0xbe, 0xad, 0xde, // unique
+        0x88, 0x77, 0x66, 0x55, 0x44, 0x33, 0x22, 0x11, // nodeid
+        0x0d, 0xd0, 0x01, 0xc0, 0xfe, 0xca, 0x01, 0xc0, // uid, gid
+        0x5e, 0xba, 0xde, 0xc0, 0x00, 0x00, 0x00, 0x00, // pid, padding
+        0xa4, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // mode, rdev
+        0x12, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // umask, padding
+        0x66, 0x6f, 0x6f, 0x2e, 0x74, 0x78, 0x74, 0x00, // name
+    ];
+
+    #[cfg(target_endian = "big")]
+    const BATCH_FORGET_REQUEST: [u8; 80] = [
+        0x00, 0x00, 0x00, 0x50, 0x00, 0x00, 0x00, 0x2a, // len, opcode
+        0xde, 0xad, 0xbe, 0xef, 0xba, 0xad, 0xd0, 0x0d, // unique
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // nodeid
+        0xc0, 0x01, 0xd0, 0x0d, 0xc0, 0x01, 0xca, 0xfe, // uid, gid
+        0xc0, 0xde, 0xba, 0x5e, 0x00, 0x00, 0x00, 0x00, // pid, padding
+        0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00, // count, dummy
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x11, // nodeid
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, // nlookup
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x22, // nodeid
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, // nlookup
+    ];
+
+    #[cfg(target_endian = "little")]
+    const BATCH_FORGET_REQUEST: [u8; 80] = [
+        0x50, 0x00, 0x00, 0x00, 0x2a, 0x00, 0x00, 0x00, // len, opcode
+        0x0d, 0xf0, 0xad, 0xba, 0xef, 0xbe, 0xad, 0xde, // unique
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // nodeid
+        0x0d, 0xd0, 0x01, 0xc0, 0xfe, 0xca, 0x01, 0xc0, // uid, gid
+        0x5e, 0xba, 0xde, 0xc0, 0x00, 0x00, 0x00, 0x00, // pid, padding
+        0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // count, dummy
+        0x11, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // nodeid
+        0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // nlookup
+        0x22, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // nodeid
+        0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // nlookup
+    ];
+
+    #[cfg(target_endian = "big")]
+    const RENAME2_REQUEST: [u8; 64] = [
+        0x00, 0x00, 0x00, 0x40, 0x00, 0x00, 0x00, 0x2d, // len, opcode
+        0xde, 0xad, 0xbe, 0xef, 0xba, 0xad, 0xd0, 0x0d, // unique
+        0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, // nodeid
+        0xc0, 0x01, 0xd0, 0x0d, 0xc0, 0x01, 0xca, 0xfe, // uid, gid
+        0xc0, 0xde, 0xba, 0x5e, 0x00, 0x00, 0x00, 0x00, // pid, padding
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x22, // newdir
+        0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00, // flags, padding
+        0x66, 0x6f, 0x6f, 0x00, 0x62, 0x61, 0x72, 0x00, // name, newname
+    ];
+
+    #[cfg(target_endian = "little")]
+    const RENAME2_REQUEST: [u8; 64] = [
+        0x40, 0x00, 0x00, 0x00, 0x2d, 0x00, 0x00, 0x00, // len, opcode
+        0x0d, 0xf0, 0xad, 0xba, 0xef, 0xbe, 0xad, 0xde, // unique
+        0x88, 0x77, 0x66, 0x55, 0x44, 0x33, 0x22, 0x11, // nodeid
+        0x0d, 0xd0, 0x01, 0xc0, 0xfe, 0xca, 0x01, 0xc0, // uid, gid
+        0x5e, 0xba, 0xde, 0xc0, 0x00, 0x00, 0x00, 0x00, // pid, padding
+        0x22, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // newdir
+        0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // flags, padding
+        0x66, 0x6f, 0x6f, 0x00, 0x62, 0x61, 0x72, 0x00, // name, newname
+    ];
+
     #[test]
     fn short_read_header() {
         match Request::try_from(&INIT_REQUEST[..20]) {
@@ -600,7 +843,7 @@ mod tests {
     #[test]
     fn mknod() {
         let req = Request::try_from(&MKNOD_REQUEST[..]).unwrap();
-        assert_eq!(req.header.len, 56);
+        assert_eq!(req.header.len as usize, MKNOD_REQUEST.len());
         assert_eq!(req.header.opcode, 8);
         assert_eq!(req.unique(), 0xdead_beef_baad_f00d);
         assert_eq!(req.nodeid(), 0x1122_3344_5566_7788);
@@ -615,4 +858,241 @@ mod tests {
             _ => panic!("Unexpected request operation"),
         }
     }
+
+    #[test]
+    fn mknod_compat() {
+        // Kernels before ABI 7.12 send the short argument without umask
+        let req = Request::parse(&MKNOD_COMPAT_REQUEST[..], 11, false).unwrap();
+        assert_eq!(req.header.len as usize, MKNOD_COMPAT_REQUEST.len());
+        match req.operation() {
+            Operation::MkNod { arg, name } => {
+                assert_eq!(arg.mode, 0o644);
+                assert_eq!(arg.umask, 0);
+                assert_eq!(*name, "foo.txt");
+            }
+            _ => panic!("Unexpected request operation"),
+        }
+    }
+
+    #[test]
+    fn security_context() {
+        let mut data = MKNOD_REQUEST.to_vec();
+        // Security context header and a record, padded to 8 bytes
+        for word in &[40u32, 1, 5, 0] {
+            data.extend_from_slice(&word.to_ne_bytes());
+        }
+        data.extend_from_slice(b"security.selinux\0label\0\0");
+        // Other extensions are skipped
+        for word in &[16u32, 32, 0, 0] {
+            data.extend_from_slice(&word.to_ne_bytes());
+        }
+        let len = data.len() as u32;
+        data[..4].copy_from_slice(&len.to_ne_bytes());
+        let req = Request::try_from(&data[..]).unwrap();
+        match req.operation() {
+            Operation::MkNod { name, .. } => assert_eq!(*name, "foo.txt"),
+            _ => panic!("Unexpected request operation"),
+        }
+        assert_eq!(
+            req.security_context(),
+            &[("security.selinux".into(), b"label".to_vec())]
+        );
+        // Without a security context (or before ABI 7.36)
+        let req = Request::try_from(&MKNOD_REQUEST[..]).unwrap();
+        assert!(req.security_context().is_empty());
+        // Truncated record
+        let len = len - 24;
+        data[..4].copy_from_slice(&len.to_ne_bytes());
+        match Request::try_from(&data[..]) {
+            Err(RequestError::InsufficientData) => (),
+            _ => panic!("Unexpected request parsing result"),
+        }
+    }
+
+    #[cfg(not(target_os = "macos"))]
+    #[test]
+    fn setxattr_ext() {
+        let request = |args: &[u32]| {
+            let mut data = MKNOD_REQUEST[..40].to_vec();
+            data[4..8].copy_from_slice(&21u32.to_ne_bytes()); // opcode (SETXATTR)
+            for word in args {
+                data.extend_from_slice(&word.to_ne_bytes());
+            }
+            data.extend_from_slice(b"user.foo\0bar");
+            let len = data.len() as u32;
+            data[..4].copy_from_slice(&len.to_ne_bytes());
+            data
+        };
+        // Without FUSE_SETXATTR_EXT, the kernel sends the short argument without setxattr_flags
+        let req = Request::parse(&request(&[3, 1])[..], 33, false).unwrap();
+        match req.operation() {
+            Operation::SetXAttr { arg, name, value } => {
+                assert_eq!((arg.size, arg.flags, arg.setxattr_flags), (3, 1, 0));
+                assert_eq!(*name, "user.foo");
+                assert_eq!(*value, b"bar");
+            }
+            _ => panic!("Unexpected request operation"),
+        }
+        let req = Request::parse(&request(&[3, 1, 1, 0])[..], 33, true).unwrap();
+        match req.operation() {
+            Operation::SetXAttr { arg, value, .. } => {
+                assert_eq!(arg.setxattr_flags, consts::FUSE_SETXATTR_ACL_KILL_SGID);
+                assert_eq!(*value, b"bar");
+            }
+            _ => panic!("Unexpected request operation"),
+        }
+    }
+
+    #[test]
+    fn batch_forget() {
+        let req = Request::try_from(&BATCH_FORGET_REQUEST[..]).unwrap();
+        assert_eq!(req.header.opcode, 42);
+        match req.operation() {
+            Operation::BatchForget { arg, nodes } => {
+                assert_eq!(arg.count, 2);
+                assert_eq!(nodes.len(), 2);
+                assert_eq!((nodes[0].nodeid, nodes[0].nlookup), (0x11, 1));
+                assert_eq!((nodes[1].nodeid, nodes[1].nlookup), (0x22, 3));
+            }
+            _ => panic!("Unexpected request operation"),
+        }
+    }
+
+    #[test]
+    fn rename2() {
+        let req = Request::try_from(&RENAME2_REQUEST[..]).unwrap();
+        assert_eq!(req.header.opcode, 45);
+        assert_eq!(req.nodeid(), 0x1122_3344_5566_7788);
+        match req.operation() {
+            Operation::Rename2 { arg, name, newname } => {
+                assert_eq!(arg.newdir, 0x22);
+                assert_eq!(arg.flags, 2);
+                assert_eq!(*name, "foo");
+                assert_eq!(*newname, "bar");
+            }
+            _ => panic!("Unexpected request operation"),
+        }
+    }
+
+    #[test]
+    fn init_ext() {
+        // Kernels before ABI 7.36 send the short argument without flags2
+        match Request::try_from(&INIT_REQUEST[..]).unwrap().operation() {
+            Operation::Init { arg } => assert_eq!(arg.flags2, 0),
+            _ => panic!("Unexpected request operation"),
+        }
+        let mut data = INIT_REQUEST.to_vec();
+        data[..4].copy_from_slice(&104u32.to_ne_bytes()); // len
+        data[52..].copy_from_slice(&(1u32 << 30).to_ne_bytes()); // flags (FUSE_INIT_EXT)
+        data.extend_from_slice(&(1u32 << 1).to_ne_bytes()); // flags2
+        data.resize(104, 0);
+        let req = Request::try_from(&data[..]).unwrap();
+        match req.operation() {
+            Operation::Init { arg } => {
+                assert_eq!(arg.minor, 8);
+                assert_eq!(arg.flags, 1 << 30);
+                assert_eq!(arg.flags2, 1 << 1);
+            }
+            _ => panic!("Unexpected request operation"),
+        }
+    }
+
+    #[test]
+    fn unknown_operation() {
+        let mut data = INIT_REQUEST.to_vec();
+        data[4..8].copy_from_slice(&9999u32.to_ne_bytes()); // opcode
+        match Request::try_from(&data[..]) {
+            Err(RequestError::UnknownOperation(9999)) => (),
+            _ => panic!("Unexpected request parsing result"),
+        }
+    }
+
+    #[test]
+    fn unsupported_operation() {
+        // Operations newer than the negotiated ABI version are unknown
+        let mut data = MKNOD_REQUEST.to_vec();
+        data[4..8].copy_from_slice(&51u32.to_ne_bytes()); // opcode (TMPFILE)
+        match Request::parse(&data[..], 36, true) {
+            Err(RequestError::UnknownOperation(51)) => (),
+            _ => panic!("Unexpected request parsing result"),
+        }
+        let req = Request::parse(&data[..], 37, true).unwrap();
+        match req.operation() {
+            Operation::TmpFile { arg } => assert_eq!(arg.flags, 0o644),
+            _ => panic!("Unexpected request operation"),
+        }
+    }
+
+    #[test]
+    fn insufficient_data() {
+        // Header claims less data than the arguments need
+        let mut data = MKNOD_REQUEST.to_vec();
+        data[..4].copy_from_slice(&44u32.to_ne_bytes()); // len
+        match Request::try_from(&data[..]) {
+            Err(RequestError::InsufficientData) => (),
+            _ => panic!("Unexpected request parsing result"),
+        }
+        // Header claims to be shorter than itself
+        data[..4].copy_from_slice(&8u32.to_ne_bytes()); // len
+        match Request::try_from(&data[..]) {
+            Err(RequestError::InsufficientData) => (),
+            _ => panic!("Unexpected request parsing result"),
+        }
+    }
+
+    #[test]
+    fn write_size_mismatch() {
+        let mut data = INIT_REQUEST[..40].to_vec();
+        data[4..8].copy_from_slice(&16u32.to_ne_bytes()); // opcode (FUSE_WRITE)
+        data.extend_from_slice(&[0; 16]); // fh, offset
+        data.extend_from_slice(&4u32.to_ne_bytes()); // size
+        data.extend_from_slice(&[0; 20]); // write_flags, lock_owner, flags, padding
+        data.extend_from_slice(b"foo"); // data (one byte short)
+        let len = data.len() as u32;
+        data[..4].copy_from_slice(&len.to_ne_bytes());
+        match Request::try_from(&data[..]) {
+            Err(RequestError::InsufficientData) => (),
+            _ => panic!("Unexpected request parsing result"),
+        }
+        data.push(b'!');
+        data[..4].copy_from_slice(&(len + 1).to_ne_bytes());
+        match Request::try_from(&data[..]).unwrap().operation() {
+            Operation::Write { arg, data } => {
+                assert_eq!(arg.size, 4);
+                assert_eq!(data, b"foo!");
+            }
+            _ => panic!("Unexpected request operation"),
+        }
+    }
+
+    #[test]
+    fn batch_forget_count() {
+        // A bogus count must not allocate or read beyond the actual nodes
+        let mut data = BATCH_FORGET_REQUEST.to_vec();
+        data[40..44].copy_from_slice(&u32::MAX.to_ne_bytes()); // count
+        match Request::try_from(&data[..]) {
+            Err(RequestError::InsufficientData) => (),
+            _ => panic!("Unexpected request parsing result"),
+        }
+    }
+
+    #[test]
+    fn garbage() {
+        // Parsing arbitrary data must never panic
+        let mut seed = 0x2545_f491_4f6c_dd1du64;
+        for _ in 0..10000 {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            let len = (seed % 128) as usize;
+            let mut data: Vec<u8> = (0..len)
+                .map(|i| (seed.rotate_left(i as u32 * 8) >> 56) as u8)
+                .collect();
+            if len >= 8 {
+                data[..4].copy_from_slice(&(len as u32).to_ne_bytes());
+                data[4..8].copy_from_slice(&(seed as u32 % 50).to_ne_bytes());
+            }
+            let _ = Request::try_from(&data[..]);
+        }
+    }
 }
@@ -0,0 +1,370 @@
+//! Kernel notifications
+//!
+//! Notifications are unsolicited messages to the kernel driver that aren't a reply to any
+//! request. They let the filesystem tell the kernel that data it has cached became stale,
+//! e.g. because the backend of the filesystem was changed by someone else.
+
+use fuse_abi::{fuse_notify_code, fuse_notify_poll_wakeup_out, fuse_out_header};
+use fuse_abi::{fuse_notify_delete_out, fuse_notify_inval_entry_out, fuse_notify_inval_inode_out};
+use fuse_abi::{fuse_notify_retrieve_out, fuse_notify_store_out};
+use libc::ENOENT;
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::io;
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::oneshot;
+
+use crate::channel::ChannelSender;
+
+/// Handle for sending notifications to the kernel driver. It can be cloned and sent to
+/// other tasks. Once the session ended, sending notifications fails with ENODEV.
+///
+/// Notifications must not be sent while handling a request that the notification affects
+/// (e.g. invalidating a directory entry from within `lookup` of its parent), since the
+/// kernel may wait for that request to complete and deadlock.
+#[derive(Clone, Debug)]
+pub struct Notifier {
+    ch: ChannelSender,
+    /// Retrieve notifications waiting for the kernel's reply
+    retrieves: Arc<Retrieves>,
+}
+
+impl Notifier {
+    /// Create a notifier that sends to the given channel
+    pub(crate) fn new(ch: ChannelSender, retrieves: Arc<Retrieves>) -> Self {
+        Self { ch, retrieves }
+    }
+
+    /// Send a notification with the given code and data
+    fn send(&self, code: fuse_notify_code, data: &[&[u8]]) -> io::Result<()> {
+        send(&self.ch, code, data)
+    }
+
+    /// Wake up the kernel's poll waiters of the given poll handle (see `PollHandle`)
+    pub fn poll_wakeup(&self, kh: u64) -> io::Result<()> {
+        poll_wakeup(&self.ch, kh)
+    }
+
+    /// Invalidate cached attributes and data of the given inode. Data in the byte range
+    /// starting at `offset` with length `len` is dropped from the page cache. A negative
+    /// offset only invalidates attributes, a length of zero invalidates up to the end of
+    /// the file.
+    pub fn inval_inode(&self, ino: u64, offset: i64, len: i64) -> io::Result<()> {
+        let arg = fuse_notify_inval_inode_out {
+            ino,
+            off: offset,
+            len,
+        };
+        self.send(fuse_notify_code::FUSE_NOTIFY_INVAL_INODE, &[as_bytes(&arg)])
+    }
+
+    /// Invalidate the directory entry with the given name in the given parent directory,
+    /// and the attributes of the parent. Fails with ENOTDIR if the parent isn't a directory.
+    pub fn inval_entry(&self, parent: u64, name: &OsStr) -> io::Result<()> {
+        let arg = fuse_notify_inval_entry_out {
+            parent,
+            namelen: name.len() as u32,
+            padding: 0,
+        };
+        self.send(
+            fuse_notify_code::FUSE_NOTIFY_INVAL_ENTRY,
+            &[as_bytes(&arg), name.as_bytes(), &[0]],
+        )
+    }
+
+    /// Tell the kernel that the directory entry with the given name in the given parent
+    /// directory, which refers to the given child inode, was deleted. Unlike `inval_entry`,
+    /// this also removes the entry from mounts and makes inotify watchers see a deletion.
+    /// Fails with ENOTDIR if the parent isn't a directory, or with ENOTEMPTY if the child
+    /// is a directory that isn't empty.
+    pub fn delete(&self, parent: u64, child: u64, name: &OsStr) -> io::Result<()> {
+        let arg = fuse_notify_delete_out {
+            parent,
+            child,
+            namelen: name.len() as u32,
+            padding: 0,
+        };
+        self.send(
+            fuse_notify_code::FUSE_NOTIFY_DELETE,
+            &[as_bytes(&arg), name.as_bytes(), &[0]],
+        )
+    }
+
+    /// Store the given data in the kernel's page cache of the given inode, starting at the
+    /// given offset. The file size is extended if the data ends beyond it. Nothing is stored
+    /// if the inode isn't cached.
+    pub fn store(&self, ino: u64, offset: u64, data: &[u8]) -> io::Result<()> {
+        let arg = fuse_notify_store_out {
+            nodeid: ino,
+            offset,
+            size: data.len() as u32,
+            padding: 0,
+        };
+        self.send(fuse_notify_code::FUSE_NOTIFY_STORE, &[as_bytes(&arg), data])
+    }
+
+    /// Retrieve up to `size` bytes of the kernel's page cache of the given inode, starting at
+    /// the given offset. The returned data ends at the first page that isn't cached, so it may
+    /// be shorter than requested, and it's empty if the inode isn't cached at all.
+    pub async fn retrieve(&self, ino: u64, offset: u64, size: u32) -> io::Result<Vec<u8>> {
+        let (notify_unique, rx) = self.retrieves.register();
+        let arg = fuse_notify_retrieve_out {
+            notify_unique,
+            nodeid: ino,
+            offset,
+            size,
+            padding: 0,
+        };
+        match send_raw(
+            &self.ch,
+            fuse_notify_code::FUSE_NOTIFY_RETRIEVE,
+            &[as_bytes(&arg)],
+        ) {
+            Ok(()) => (),
+            Err(err) => {
+                self.retrieves.cancel(notify_unique);
+                return match err.raw_os_error() {
+                    Some(ENOENT) => Ok(Vec::new()),
+                    _ => Err(err),
+                };
+            }
+        }
+        // The reply is dropped if the session ends before the kernel replied
+        rx.await
+            .map_err(|_| io::Error::from_raw_os_error(libc::ENODEV))
+    }
+}
+
+/// Handle of a poll request that asked to be notified about readiness changes. It can be
+/// kept after replying to the poll request and be used from any task to wake up the
+/// processes polling the file, which makes the kernel send a new poll request.
+#[derive(Clone, Debug)]
+pub struct PollHandle {
+    kh: u64,
+    ch: ChannelSender,
+}
+
+impl PollHandle {
+    /// Create a poll handle for the given kernel handle that sends to the given channel
+    pub(crate) fn new(kh: u64, ch: ChannelSender) -> Self {
+        Self { kh, ch }
+    }
+
+    /// Kernel handle of this poll handle
+    pub fn kh(&self) -> u64 {
+        self.kh
+    }
+
+    /// Notify the kernel that the readiness of the file changed
+    pub fn notify(&self) -> io::Result<()> {
+        poll_wakeup(&self.ch, self.kh)
+    }
+}
+
+/// Wake up the kernel's poll waiters of the given poll handle
+fn poll_wakeup(ch: &ChannelSender, kh: u64) -> io::Result<()> {
+    let arg = fuse_notify_poll_wakeup_out { kh };
+    send(ch, fuse_notify_code::FUSE_POLL, &[as_bytes(&arg)])
+}
+
+/// Send a notification with the given code and data. The kernel returns ENOENT if it
+/// doesn't have the inode or entry in its cache, which means there's nothing to
+/// invalidate, so it isn't treated as an error.
+fn send(ch: &ChannelSender, code: fuse_notify_code, data: &[&[u8]]) -> io::Result<()> {
+    match send_raw(ch, code, data) {
+        Err(err) if err.raw_os_error() == Some(ENOENT) => Ok(()),
+        res => res,
+    }
+}
+
+/// Send a notification with the given code and data
+fn send_raw(ch: &ChannelSender, code: fuse_notify_code, data: &[&[u8]]) -> io::Result<()> {
+    let len = data.iter().map(|d| d.len()).sum::<usize>();
+    let header = fuse_out_header {
+        len: (mem::size_of::<fuse_out_header>() + len) as u32,
+        error: code as i32,
+        unique: 0,
+    };
+    let mut sendbytes = data.to_vec();
+    sendbytes.insert(0, as_bytes(&header));
+    ch.send(&sendbytes)
+}
+
+/// Retrieve notifications waiting for the kernel's reply. The kernel answers a retrieve
+/// notification with a FUSE_NOTIFY_REPLY request that carries the notification's unique id.
+#[derive(Debug, Default)]
+pub(crate) struct Retrieves {
+    /// Last used notification unique id
+    last_unique: AtomicU64,
+    /// Senders for the data of pending retrieves
+    pending: Mutex<HashMap<u64, oneshot::Sender<Vec<u8>>>>,
+}
+
+impl Retrieves {
+    /// Register a new retrieve. Returns its unique id and a receiver for the data.
+    fn register(&self) -> (u64, oneshot::Receiver<Vec<u8>>) {
+        let unique = self.last_unique.fetch_add(1, Ordering::Relaxed) + 1;
+        let (tx, rx) = oneshot::channel();
+        self.pending.lock().unwrap().insert(unique, tx);
+        (unique, rx)
+    }
+
+    /// Forget a retrieve that couldn't be sent
+    fn cancel(&self, unique: u64) {
+        self.pending.lock().unwrap().remove(&unique);
+    }
+
+    /// Pass the data of the kernel's reply to the retrieve with the given unique id.
+    /// Returns false if no such retrieve is pending.
+    pub fn complete(&self, unique: u64, data: Vec<u8>) -> bool {
+        match self.pending.lock().unwrap().remove(&unique) {
+            // The receiver may have gone away meanwhile, which is fine
+            Some(tx) => {
+                let _ = tx.send(data);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Abort all pending retrieves (when the session ends)
+    pub fn clear(&self) {
+        self.pending.lock().unwrap().clear();
+    }
+}
+
+/// Serialize a fuse_*_out type to bytes (memory copy)
+fn as_bytes<T>(data: &T) -> &[u8] {
+    unsafe { std::slice::from_raw_parts(data as *const T as *const u8, mem::size_of::<T>()) }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Retrieves;
+    use super::{Notifier, PollHandle};
+    use crate::channel::ChannelSender;
+    use std::ffi::OsStr;
+    use std::os::unix::io::{FromRawFd, OwnedFd};
+    use std::sync::Arc;
+
+    /// Run the given function with a notifier that sends to a pipe and return what was sent
+    fn sent<F: FnOnce(&Notifier)>(f: F) -> Vec<u8> {
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        let fd = Arc::new(unsafe { OwnedFd::from_raw_fd(fds[1]) });
+        f(&Notifier::new(
+            ChannelSender::from_fd(&fd),
+            Default::default(),
+        ));
+        let mut buf = vec![0u8; 4096];
+        let len = unsafe { libc::read(fds[0], buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+        unsafe {
+            libc::close(fds[0]);
+        }
+        buf.truncate(len as usize);
+        buf
+    }
+
+    #[test]
+    fn poll_wakeup() {
+        let data = sent(|n| PollHandle::new(0x11, n.ch.clone()).notify().unwrap());
+        assert_eq!(
+            data,
+            [
+                0x18, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, // len, code
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // unique
+                0x11, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // kh
+            ]
+        );
+    }
+
+    #[test]
+    fn session_ended() {
+        // Notifiers and poll handles may outlive the session, but don't send anymore
+        let notifier = Notifier::new(ChannelSender::closed(), Default::default());
+        let err = notifier.inval_inode(0x11, 0, -1).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::ENODEV));
+        let err = PollHandle::new(0x11, ChannelSender::closed())
+            .notify()
+            .unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::ENODEV));
+    }
+
+    #[test]
+    fn inval_inode() {
+        let data = sent(|n| n.inval_inode(0x11, 0x22, -1).unwrap());
+        assert_eq!(
+            data,
+            [
+                0x28, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, // len, code
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // unique
+                0x11, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // ino
+                0x22, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // off
+                0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, // len
+            ]
+        );
+    }
+
+    #[test]
+    fn inval_entry() {
+        let data = sent(|n| n.inval_entry(0x11, OsStr::new("foo")).unwrap());
+        assert_eq!(
+            data,
+            [
+                0x24, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, // len, code
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // unique
+                0x11, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // parent
+                0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // namelen, padding
+                0x66, 0x6f, 0x6f, 0x00, // name
+            ]
+        );
+    }
+
+    #[test]
+    fn delete() {
+        let data = sent(|n| n.delete(0x11, 0x22, OsStr::new("foo")).unwrap());
+        assert_eq!(
+            data,
+            [
+                0x2c, 0x00, 0x00, 0x00, 0x06, 0x00, 0x00, 0x00, // len, code
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // unique
+                0x11, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // parent
+                0x22, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // child
+                0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // namelen, padding
+                0x66, 0x6f, 0x6f, 0x00, // name
+            ]
+        );
+    }
+
+    #[test]
+    fn store() {
+        let data = sent(|n| n.store(0x11, 0x22, b"foo").unwrap());
+        assert_eq!(
+            data,
+            [
+                0x2b, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, // len, code
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // unique
+                0x11, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // nodeid
+                0x22, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // offset
+                0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // size, padding
+                0x66, 0x6f, 0x6f, // data
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn retrieve_reply() {
+        let retrieves = Retrieves::default();
+        let (unique1, rx1) = retrieves.register();
+        let (unique2, rx2) = retrieves.register();
+        assert_ne!(unique1, unique2);
+        assert!(retrieves.complete(unique2, b"bar".to_vec()));
+        assert!(!retrieves.complete(unique2, b"baz".to_vec()));
+        assert_eq!(rx2.await.unwrap(), b"bar");
+        retrieves.clear();
+        assert!(rx1.await.is_err());
+    }
+}
@@ -0,0 +1,84 @@
+//! Passthrough I/O
+//!
+//! With the `FUSE_PASSTHROUGH` capability, the kernel can serve reads and writes of an open
+//! file directly from a backing file, without sending requests to the filesystem. The
+//! filesystem registers the backing file with the kernel and passes the resulting backing id
+//! with its reply to `open` or `create`. Metadata operations are still sent to the
+//! filesystem.
+
+use libc::ENODEV;
+use log::error;
+use std::io;
+use std::os::unix::io::AsRawFd;
+
+use crate::channel::ChannelSender;
+
+/// A backing file registered with the kernel for passthrough I/O. The registration is
+/// removed when it is dropped. Files that were opened with it keep using the backing file
+/// until they're released, so it can be dropped right after replying, or kept to open
+/// further files of the same inode with it. It may outlive the session, in which case the
+/// kernel already removed the registration together with the connection.
+#[derive(Debug)]
+pub struct BackingId {
+    /// Channel the file was registered on, which only refers to the fd weakly
+    ch: ChannelSender,
+    id: u32,
+}
+
+impl BackingId {
+    /// Register the given file as backing file (see `Request::open_backing`)
+    pub(crate) fn open<F: AsRawFd>(ch: ChannelSender, file: &F) -> io::Result<Self> {
+        let id = ch.open_backing(file.as_raw_fd())?;
+        Ok(Self { ch, id })
+    }
+
+    /// Backing id the kernel assigned to the file
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+}
+
+impl Drop for BackingId {
+    fn drop(&mut self) {
+        match self.ch.close_backing(self.id) {
+            Ok(()) => (),
+            // The session ended, so there's nothing to close (and the fd may be reused)
+            Err(err) if err.raw_os_error() == Some(ENODEV) => (),
+            Err(err) => error!("Failed to close backing file {}: {}", self.id, err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::BackingId;
+    use crate::channel::ChannelSender;
+    use std::fs::File;
+    use std::os::unix::io::{FromRawFd, OwnedFd};
+    use std::sync::Arc;
+
+    #[test]
+    fn open_without_fuse() {
+        // Backing files can only be registered on a FUSE device
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        let fd = Arc::new(unsafe { OwnedFd::from_raw_fd(fds[1]) });
+        let file = File::open("/dev/null").unwrap();
+        assert!(BackingId::open(ChannelSender::from_fd(&fd), &file).is_err());
+        unsafe {
+            libc::close(fds[0]);
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn drop_after_session() {
+        // Dropping a backing id after the session ended doesn't touch the closed fd
+        let err = ChannelSender::closed().close_backing(1).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::ENODEV));
+        drop(BackingId {
+            ch: ChannelSender::closed(),
+            id: 1,
+        });
+    }
+}
@@ -6,23 +6,31 @@
 //! data without cloning the data. A reply *must always* be used (by calling either ok() or
 //! error() exactly once).
 
-use fuse_abi::fuse_getxattr_out;
+use fuse_abi::consts::{FUSE_IOCTL_32BIT, FUSE_IOCTL_MAX_IOV};
+use fuse_abi::consts::{FUSE_IOCTL_RETRY, FUSE_IOCTL_UNRESTRICTED};
 #[cfg(target_os = "macos")]
 use fuse_abi::fuse_getxtimes_out;
 use fuse_abi::{fuse_attr, fuse_attr_out, fuse_entry_out, fuse_file_lock, fuse_kstatfs};
 use fuse_abi::{fuse_bmap_out, fuse_lk_out, fuse_open_out, fuse_statfs_out, fuse_write_out};
-use fuse_abi::{fuse_dirent, fuse_out_header};
-use libc::{c_int, EIO, S_IFBLK, S_IFCHR, S_IFDIR, S_IFIFO, S_IFLNK, S_IFREG, S_IFSOCK};
+use fuse_abi::{fuse_dirent, fuse_direntplus, fuse_getxattr_out, fuse_out_header};
+use fuse_abi::{fuse_ioctl_in, fuse_ioctl_iovec, fuse_ioctl_out, fuse_lseek_out, fuse_poll_out};
+use fuse_abi::{fuse_statx, fuse_statx_out, fuse_sx_time};
+use fuse_abi::{FUSE_COMPAT_ATTR_OUT_SIZE, FUSE_COMPAT_ENTRY_OUT_SIZE, FUSE_KERNEL_MINOR_VERSION};
+use libc::{c_int, EINVAL, EIO, ENOMEM, ENOSYS};
+use libc::{S_IFBLK, S_IFCHR, S_IFDIR, S_IFIFO, S_IFLNK, S_IFREG, S_IFSOCK};
 use log::warn;
 use std::convert::AsRef;
 use std::ffi::OsStr;
 use std::fmt;
 use std::marker::PhantomData;
 use std::os::unix::ffi::OsStrExt;
+use std::sync::atomic::{AtomicBool, Ordering::Relaxed};
+use std::sync::Arc;
 use std::time::{Duration, SystemTime, SystemTimeError, UNIX_EPOCH};
 use std::{mem, ptr, slice};
 
-use crate::{FileAttr, FileType};
+use crate::passthrough::BackingId;
+use crate::{FileAttr, FileType, OpenOptions};
 
 /// Generic reply callback to send data
 pub trait ReplySender: Send + 'static {
@@ -42,6 +50,36 @@ pub trait Reply {
     fn new<S: ReplySender>(unique: u64, sender: S) -> Self;
 }
 
+/// Reply whose data layout depends on the protocol version negotiated with the kernel
+pub(crate) trait VersionedReply: Reply {
+    /// Use the layout of the given minor version of the FUSE protocol
+    fn set_proto_minor(&mut self, proto_minor: u32);
+}
+
+/// Error to reply with if the given open flags aren't supported by the given minor version
+/// of the FUSE protocol
+fn unsupported_open_options(flags: OpenOptions, proto_minor: u32) -> Option<c_int> {
+    let unsupported = flags.unsupported(proto_minor);
+    if unsupported.is_empty() {
+        return None;
+    }
+    warn!(
+        "Open flags {:#x} not supported by FUSE ABI 7.{}, failing open",
+        unsupported.bits(),
+        proto_minor
+    );
+    Some(EINVAL)
+}
+
+/// Error to reply with if the given open flags ask for passthrough without a backing file
+fn passthrough_without_backing(flags: OpenOptions) -> Option<c_int> {
+    if !flags.contains(OpenOptions::PASSTHROUGH) {
+        return None;
+    }
+    warn!("Open flag FOPEN_PASSTHROUGH needs a backing file, failing open");
+    Some(EINVAL)
+}
+
 /// Serialize an arbitrary type to bytes (memory copy, useful for fuse_*_out types)
 fn as_bytes<T, U, F: FnOnce(&[&[u8]]) -> U>(data: &T, f: F) -> U {
     let len = mem::size_of::<T>();
@@ -73,8 +111,7 @@ fn mode_from_kind_and_perm(kind: FileType, perm: u16) -> u32 {
         FileType::RegularFile => S_IFREG,
         FileType::Symlink => S_IFLNK,
         FileType::Socket => S_IFSOCK,
-    }) as u32
-        | perm as u32
+    }) | perm as u32
 }
 
 /// Returns a fuse_attr from FileAttr
@@ -104,6 +141,8 @@ fn fuse_attr_from_attr(attr: &FileAttr) -> fuse_attr {
         gid: attr.gid,
         rdev: attr.rdev,
         flags: attr.flags,
+        blksize: 0,
+        padding: 0,
     }
 }
 
@@ -130,9 +169,7 @@ fn fuse_attr_from_attr(attr: &FileAttr) -> fuse_attr {
         uid: attr.uid,
         gid: attr.gid,
         rdev: attr.rdev,
-        #[cfg(feature = "abi-7-9")]
         blksize: 0,
-        #[cfg(feature = "abi-7-9")]
         padding: 0,
     }
 }
@@ -155,7 +192,7 @@ impl<T> Reply for ReplyRaw<T> {
     fn new<S: ReplySender>(unique: u64, sender: S) -> ReplyRaw<T> {
         let sender = Box::new(sender);
         ReplyRaw {
-            unique: unique,
+            unique,
             sender: Some(sender),
             marker: PhantomData,
         }
@@ -188,6 +225,24 @@ impl<T> ReplyRaw<T> {
         })
     }
 
+    /// Reply to a request with the first `len` bytes of the given type (for kernels that
+    /// expect an older and shorter version of it)
+    pub(crate) fn ok_truncated(mut self, data: &T, len: usize) {
+        as_bytes(data, |bytes| {
+            let bytes: Vec<_> = bytes.iter().map(|b| &b[..len.min(b.len())]).collect();
+            self.send(0, &bytes);
+        })
+    }
+
+    /// Reply to a request with the given type, followed by the given data
+    pub fn ok_with_data(mut self, data: &T, extra: &[u8]) {
+        as_bytes(data, |bytes| {
+            let mut sendbytes = bytes.to_vec();
+            sendbytes.push(extra);
+            self.send(0, &sendbytes);
+        })
+    }
+
     /// Reply to a request with the given error code
     pub fn error(mut self, err: c_int) {
         self.send(err, &[]);
@@ -268,28 +323,42 @@ impl ReplyData {
 #[derive(Debug)]
 pub struct ReplyEntry {
     reply: ReplyRaw<fuse_entry_out>,
+    proto_minor: u32,
 }
 
 impl Reply for ReplyEntry {
     fn new<S: ReplySender>(unique: u64, sender: S) -> ReplyEntry {
         ReplyEntry {
             reply: Reply::new(unique, sender),
+            proto_minor: FUSE_KERNEL_MINOR_VERSION,
         }
     }
 }
 
+impl VersionedReply for ReplyEntry {
+    fn set_proto_minor(&mut self, proto_minor: u32) {
+        self.proto_minor = proto_minor;
+    }
+}
+
 impl ReplyEntry {
     /// Reply to a request with the given entry
     pub fn entry(self, ttl: &Duration, attr: &FileAttr, generation: u64) {
-        self.reply.ok(&fuse_entry_out {
+        let entry = fuse_entry_out {
             nodeid: attr.ino,
-            generation: generation,
+            generation,
             entry_valid: ttl.as_secs(),
             attr_valid: ttl.as_secs(),
             entry_valid_nsec: ttl.subsec_nanos(),
             attr_valid_nsec: ttl.subsec_nanos(),
             attr: fuse_attr_from_attr(attr),
-        });
+        };
+        // Kernels before ABI 7.9 expect attributes without blksize
+        if self.proto_minor < 9 {
+            self.reply.ok_truncated(&entry, FUSE_COMPAT_ENTRY_OUT_SIZE);
+        } else {
+            self.reply.ok(&entry);
+        }
     }
 
     /// Reply to a request with the given error code
@@ -304,25 +373,39 @@ impl ReplyEntry {
 #[derive(Debug)]
 pub struct ReplyAttr {
     reply: ReplyRaw<fuse_attr_out>,
+    proto_minor: u32,
 }
 
 impl Reply for ReplyAttr {
     fn new<S: ReplySender>(unique: u64, sender: S) -> ReplyAttr {
         ReplyAttr {
             reply: Reply::new(unique, sender),
+            proto_minor: FUSE_KERNEL_MINOR_VERSION,
         }
     }
 }
 
+impl VersionedReply for ReplyAttr {
+    fn set_proto_minor(&mut self, proto_minor: u32) {
+        self.proto_minor = proto_minor;
+    }
+}
+
 impl ReplyAttr {
     /// Reply to a request with the given attribute
     pub fn attr(self, ttl: &Duration, attr: &FileAttr) {
-        self.reply.ok(&fuse_attr_out {
+        let attr = fuse_attr_out {
             attr_valid: ttl.as_secs(),
             attr_valid_nsec: ttl.subsec_nanos(),
             dummy: 0,
             attr: fuse_attr_from_attr(attr),
-        });
+        };
+        // Kernels before ABI 7.9 expect attributes without blksize
+        if self.proto_minor < 9 {
+            self.reply.ok_truncated(&attr, FUSE_COMPAT_ATTR_OUT_SIZE);
+        } else {
+            self.reply.ok(&attr);
+        }
     }
 
     /// Reply to a request with the given error code
@@ -376,28 +459,104 @@ impl ReplyXTimes {
 #[derive(Debug)]
 pub struct ReplyOpen {
     reply: ReplyRaw<fuse_open_out>,
+    proto_minor: u32,
+    zero_message: Option<Arc<ZeroMessageOpen>>,
 }
 
 impl Reply for ReplyOpen {
     fn new<S: ReplySender>(unique: u64, sender: S) -> ReplyOpen {
         ReplyOpen {
             reply: Reply::new(unique, sender),
+            proto_minor: FUSE_KERNEL_MINOR_VERSION,
+            zero_message: None,
+        }
+    }
+}
+
+impl VersionedReply for ReplyOpen {
+    fn set_proto_minor(&mut self, proto_minor: u32) {
+        self.proto_minor = proto_minor;
+    }
+}
+
+/// State of zero-message opens (`FUSE_NO_OPEN_SUPPORT` or `FUSE_NO_OPENDIR_SUPPORT`). If the
+/// capability is enabled and the filesystem replies ENOSYS to an open, the kernel treats it
+/// as success and doesn't send further opens. Once that happened, opens that are still in
+/// flight succeed the same way without calling the filesystem.
+#[derive(Debug, Default)]
+pub(crate) struct ZeroMessageOpen {
+    /// True if the capability was enabled on init
+    enabled: AtomicBool,
+    /// True once the filesystem replied ENOSYS
+    active: AtomicBool,
+}
+
+impl ZeroMessageOpen {
+    /// Enable or disable zero-message opens, as negotiated on init
+    pub(crate) fn enable(&self, enabled: bool) {
+        self.enabled.store(enabled, Relaxed);
+    }
+
+    /// Returns true if opens are zero-message since the filesystem replied ENOSYS
+    pub(crate) fn is_active(&self) -> bool {
+        self.active.load(Relaxed)
+    }
+
+    /// Remember the error the filesystem replied to an open with
+    fn replied(&self, err: c_int) {
+        if err == ENOSYS && self.enabled.load(Relaxed) {
+            self.active.store(true, Relaxed);
         }
     }
 }
 
 impl ReplyOpen {
-    /// Reply to a request with the given open result
-    pub fn opened(self, fh: u64, flags: u32) {
+    /// Remember an ENOSYS reply in the given zero-message open state
+    pub(crate) fn zero_message(mut self, state: &Arc<ZeroMessageOpen>) -> ReplyOpen {
+        self.zero_message = Some(state.clone());
+        self
+    }
+
+    /// Returns true if the kernel supports all of the given flags
+    pub fn supports(&self, flags: OpenOptions) -> bool {
+        flags.unsupported(self.proto_minor).is_empty()
+    }
+
+    /// Reply to a request with the given open result. If the kernel doesn't support all of
+    /// the given flags (see `supports`), the open fails with EINVAL instead. So does passing
+    /// `OpenOptions::PASSTHROUGH`, which needs a backing file (see `opened_passthrough`).
+    pub fn opened(self, fh: u64, flags: OpenOptions) {
+        if let Some(err) = passthrough_without_backing(flags) {
+            self.reply.error(err);
+            return;
+        }
+        self.open(fh, flags, 0);
+    }
+
+    /// Reply to a request with the given open result and let the kernel serve reads and
+    /// writes from the given backing file (see `Request::open_backing`). Needs ABI 7.40.
+    pub fn opened_passthrough(self, fh: u64, flags: OpenOptions, backing: &BackingId) {
+        self.open(fh, flags | OpenOptions::PASSTHROUGH, backing.id() as i32);
+    }
+
+    fn open(self, fh: u64, flags: OpenOptions, backing_id: i32) {
+        if let Some(err) = unsupported_open_options(flags, self.proto_minor) {
+            self.reply.error(err);
+            return;
+        }
         self.reply.ok(&fuse_open_out {
-            fh: fh,
-            open_flags: flags,
-            padding: 0,
+            fh,
+            open_flags: flags.bits(),
+            backing_id,
         });
     }
 
-    /// Reply to a request with the given error code
+    /// Reply to a request with the given error code. With zero-message opens, ENOSYS lets
+    /// this and all further opens succeed without a file handle.
     pub fn error(self, err: c_int) {
+        if let Some(state) = &self.zero_message {
+            state.replied(err);
+        }
         self.reply.error(err);
     }
 }
@@ -421,10 +580,7 @@ impl Reply for ReplyWrite {
 impl ReplyWrite {
     /// Reply to a request with the given open result
     pub fn written(self, size: u32) {
-        self.reply.ok(&fuse_write_out {
-            size: size,
-            padding: 0,
-        });
+        self.reply.ok(&fuse_write_out { size, padding: 0 });
     }
 
     /// Reply to a request with the given error code
@@ -451,6 +607,7 @@ impl Reply for ReplyStatfs {
 
 impl ReplyStatfs {
     /// Reply to a request with the given open result
+    #[allow(clippy::too_many_arguments)]
     pub fn statfs(
         self,
         blocks: u64,
@@ -464,14 +621,14 @@ impl ReplyStatfs {
     ) {
         self.reply.ok(&fuse_statfs_out {
             st: fuse_kstatfs {
-                blocks: blocks,
-                bfree: bfree,
-                bavail: bavail,
-                files: files,
-                ffree: ffree,
-                bsize: bsize,
-                namelen: namelen,
-                frsize: frsize,
+                blocks,
+                bfree,
+                bavail,
+                files,
+                ffree,
+                bsize,
+                namelen,
+                frsize,
                 padding: 0,
                 spare: [0; 6],
             },
@@ -490,35 +647,101 @@ impl ReplyStatfs {
 #[derive(Debug)]
 pub struct ReplyCreate {
     reply: ReplyRaw<(fuse_entry_out, fuse_open_out)>,
+    proto_minor: u32,
 }
 
 impl Reply for ReplyCreate {
     fn new<S: ReplySender>(unique: u64, sender: S) -> ReplyCreate {
         ReplyCreate {
             reply: Reply::new(unique, sender),
+            proto_minor: FUSE_KERNEL_MINOR_VERSION,
         }
     }
 }
 
+impl VersionedReply for ReplyCreate {
+    fn set_proto_minor(&mut self, proto_minor: u32) {
+        self.proto_minor = proto_minor;
+    }
+}
+
 impl ReplyCreate {
-    /// Reply to a request with the given entry
-    pub fn created(self, ttl: &Duration, attr: &FileAttr, generation: u64, fh: u64, flags: u32) {
-        self.reply.ok(&(
-            fuse_entry_out {
-                nodeid: attr.ino,
-                generation: generation,
-                entry_valid: ttl.as_secs(),
-                attr_valid: ttl.as_secs(),
-                entry_valid_nsec: ttl.subsec_nanos(),
-                attr_valid_nsec: ttl.subsec_nanos(),
-                attr: fuse_attr_from_attr(attr),
-            },
-            fuse_open_out {
-                fh: fh,
-                open_flags: flags,
-                padding: 0,
-            },
-        ));
+    /// Returns true if the kernel supports all of the given flags
+    pub fn supports(&self, flags: OpenOptions) -> bool {
+        flags.unsupported(self.proto_minor).is_empty()
+    }
+
+    /// Reply to a request with the given entry and open result. If the kernel doesn't support
+    /// all of the given flags (see `supports`), the create fails with EINVAL instead. So does
+    /// passing `OpenOptions::PASSTHROUGH`, which needs a backing file (see
+    /// `created_passthrough`).
+    pub fn created(
+        self,
+        ttl: &Duration,
+        attr: &FileAttr,
+        generation: u64,
+        fh: u64,
+        flags: OpenOptions,
+    ) {
+        if let Some(err) = passthrough_without_backing(flags) {
+            self.reply.error(err);
+            return;
+        }
+        self.create(ttl, attr, generation, fh, flags, 0);
+    }
+
+    /// Reply to a request with the given entry and open result and let the kernel serve
+    /// reads and writes from the given backing file (see `Request::open_backing`). Needs
+    /// ABI 7.40.
+    pub fn created_passthrough(
+        self,
+        ttl: &Duration,
+        attr: &FileAttr,
+        generation: u64,
+        fh: u64,
+        flags: OpenOptions,
+        backing: &BackingId,
+    ) {
+        let flags = flags | OpenOptions::PASSTHROUGH;
+        self.create(ttl, attr, generation, fh, flags, backing.id() as i32);
+    }
+
+    fn create(
+        mut self,
+        ttl: &Duration,
+        attr: &FileAttr,
+        generation: u64,
+        fh: u64,
+        flags: OpenOptions,
+        backing_id: i32,
+    ) {
+        if let Some(err) = unsupported_open_options(flags, self.proto_minor) {
+            self.reply.error(err);
+            return;
+        }
+        let entry = fuse_entry_out {
+            nodeid: attr.ino,
+            generation,
+            entry_valid: ttl.as_secs(),
+            attr_valid: ttl.as_secs(),
+            entry_valid_nsec: ttl.subsec_nanos(),
+            attr_valid_nsec: ttl.subsec_nanos(),
+            attr: fuse_attr_from_attr(attr),
+        };
+        let open = fuse_open_out {
+            fh,
+            open_flags: flags.bits(),
+            backing_id,
+        };
+        // Kernels before ABI 7.9 expect attributes without blksize
+        if self.proto_minor < 9 {
+            let mut data = as_bytes(&entry, |bytes| bytes.concat());
+            data.truncate(FUSE_COMPAT_ENTRY_OUT_SIZE);
+            as_bytes(&open, |bytes| data.extend(bytes.concat()));
+            self.reply.send(0, &[&data]);
+        } else {
+            self.reply.ok(&(entry, open));
+        }
     }
 
     /// Reply to a request with the given error code
@@ -548,10 +771,10 @@ impl ReplyLock {
     pub fn locked(self, start: u64, end: u64, typ: u32, pid: u32) {
         self.reply.ok(&fuse_lk_out {
             lk: fuse_file_lock {
-                start: start,
-                end: end,
-                typ: typ,
-                pid: pid,
+                start,
+                end,
+                typ,
+                pid,
             },
         });
     }
@@ -581,7 +804,254 @@ impl Reply for ReplyBmap {
 impl ReplyBmap {
     /// Reply to a request with the given open result
     pub fn bmap(self, block: u64) {
-        self.reply.ok(&fuse_bmap_out { block: block });
+        self.reply.ok(&fuse_bmap_out { block });
+    }
+
+    /// Reply to a request with the given error code
+    pub fn error(self, err: c_int) {
+        self.reply.error(err);
+    }
+}
+
+///
+/// Lseek Reply
+///
+#[derive(Debug)]
+pub struct ReplyLseek {
+    reply: ReplyRaw<fuse_lseek_out>,
+}
+
+impl Reply for ReplyLseek {
+    fn new<S: ReplySender>(unique: u64, sender: S) -> ReplyLseek {
+        ReplyLseek {
+            reply: Reply::new(unique, sender),
+        }
+    }
+}
+
+impl ReplyLseek {
+    /// Reply to a request with the resulting offset
+    pub fn offset(self, offset: i64) {
+        self.reply.ok(&fuse_lseek_out {
+            offset: offset as u64,
+        });
+    }
+
+    /// Reply to a request with the given error code
+    pub fn error(self, err: c_int) {
+        self.reply.error(err);
+    }
+}
+
+/// Fields of a statx reply that are filled in (STATX_BASIC_STATS | STATX_BTIME)
+const STATX_MASK: u32 = 0x0fff;
+
+/// Returns a fuse_sx_time from a SystemTime. Times before the epoch have negative seconds
+/// and nanoseconds counting forward from there, like a timespec.
+fn sx_time_from_system_time(system_time: &SystemTime) -> fuse_sx_time {
+    let (tv_sec, tv_nsec) = match system_time.duration_since(UNIX_EPOCH) {
+        Ok(duration) => (duration.as_secs() as i64, duration.subsec_nanos()),
+        Err(err) => {
+            let duration = err.duration();
+            match duration.subsec_nanos() {
+                0 => (-(duration.as_secs() as i64), 0),
+                nanos => (-(duration.as_secs() as i64) - 1, 1_000_000_000 - nanos),
+            }
+        }
+    };
+    fuse_sx_time {
+        tv_sec,
+        tv_nsec,
+        reserved: 0,
+    }
+}
+
+///
+/// Statx Reply
+///
+#[derive(Debug)]
+pub struct ReplyStatx {
+    reply: ReplyRaw<fuse_statx_out>,
+}
+
+impl Reply for ReplyStatx {
+    fn new<S: ReplySender>(unique: u64, sender: S) -> ReplyStatx {
+        ReplyStatx {
+            reply: Reply::new(unique, sender),
+        }
+    }
+}
+
+impl ReplyStatx {
+    /// Reply to a request with the given attributes, including the creation time (btime).
+    /// Attributes are `STATX_ATTR_*` flags (e.g. immutable or append-only) and the attributes
+    /// mask tells which of them the filesystem supports.
+    pub fn statx(self, ttl: &Duration, attr: &FileAttr, attributes: u64, attributes_mask: u64) {
+        // Device numbers are in the kernel's 32 bit encoding (see new_decode_dev)
+        let rdev = attr.rdev;
+        self.reply.ok(&fuse_statx_out {
+            attr_valid: ttl.as_secs(),
+            attr_valid_nsec: ttl.subsec_nanos(),
+            flags: 0,
+            spare: [0; 2],
+            stat: fuse_statx {
+                mask: STATX_MASK,
+                blksize: 0,
+                attributes,
+                nlink: attr.nlink,
+                uid: attr.uid,
+                gid: attr.gid,
+                mode: mode_from_kind_and_perm(attr.kind, attr.perm) as u16,
+                spare0: [0; 1],
+                ino: attr.ino,
+                size: attr.size,
+                blocks: attr.blocks,
+                attributes_mask,
+                atime: sx_time_from_system_time(&attr.atime),
+                btime: sx_time_from_system_time(&attr.crtime),
+                ctime: sx_time_from_system_time(&attr.ctime),
+                mtime: sx_time_from_system_time(&attr.mtime),
+                rdev_major: (rdev & 0xfff00) >> 8,
+                rdev_minor: (rdev & 0xff) | ((rdev >> 12) & 0xfff00),
+                dev_major: 0,
+                dev_minor: 0,
+                spare2: [0; 14],
+            },
+        });
+    }
+
+    /// Reply to a request with the given error code
+    pub fn error(self, err: c_int) {
+        self.reply.error(err);
+    }
+}
+
+///
+/// Ioctl Reply
+///
+#[derive(Debug)]
+pub struct ReplyIoctl {
+    reply: ReplyRaw<fuse_ioctl_out>,
+    /// Flags of the ioctl request (FUSE_IOCTL_*)
+    flags: u32,
+    /// Maximum size of the output data
+    out_size: u32,
+}
+
+impl Reply for ReplyIoctl {
+    fn new<S: ReplySender>(unique: u64, sender: S) -> ReplyIoctl {
+        ReplyIoctl {
+            reply: Reply::new(unique, sender),
+            flags: 0,
+            out_size: u32::MAX,
+        }
+    }
+}
+
+impl ReplyIoctl {
+    /// Take the flags and the output size from the given ioctl request
+    pub(crate) fn request(mut self, arg: &fuse_ioctl_in) -> ReplyIoctl {
+        self.flags = arg.flags;
+        self.out_size = arg.out_size;
+        self
+    }
+
+    /// Reply to a request with the given ioctl result and output data. If the data is
+    /// larger than the output size of the request, the ioctl fails with EIO instead.
+    pub fn ioctl(mut self, result: i32, data: &[u8]) {
+        if data.len() > self.out_size as usize {
+            warn!("Ioctl output data exceeds the output size of the request");
+            return self.reply.error(EIO);
+        }
+        let out = fuse_ioctl_out {
+            result,
+            flags: 0,
+            in_iovs: 0,
+            out_iovs: 0,
+        };
+        as_bytes(&out, |bytes| {
+            let mut sendbytes = bytes.to_vec();
+            sendbytes.push(data);
+            self.reply.send(0, &sendbytes);
+        });
+    }
+
+    /// Reply to an unrestricted ioctl (FUSE_IOCTL_UNRESTRICTED in flags) by asking the kernel
+    /// to retry it with the given memory areas of the calling process as input and output
+    /// data. Areas are given as (address, length) pairs, e.g. to follow pointers in the ioctl
+    /// argument. Since restricted ioctls can't be retried and a 32-bit caller
+    /// (FUSE_IOCTL_32BIT in flags) can't address memory beyond 4 GiB, such replies are
+    /// turned into an error.
+    pub fn retry(mut self, in_iovs: &[(u64, u64)], out_iovs: &[(u64, u64)]) {
+        if self.flags & FUSE_IOCTL_UNRESTRICTED == 0 {
+            warn!("Can't retry restricted ioctl");
+            return self.reply.error(EIO);
+        }
+        if in_iovs.len() + out_iovs.len() > FUSE_IOCTL_MAX_IOV as usize {
+            warn!("Too many iovecs to retry ioctl");
+            return self.reply.error(ENOMEM);
+        }
+        let iovs: Vec<_> = in_iovs
+            .iter()
+            .chain(out_iovs)
+            .map(|&(base, len)| fuse_ioctl_iovec { base, len })
+            .collect();
+        if self.flags & FUSE_IOCTL_32BIT != 0
+            && iovs
+                .iter()
+                .any(|iov| iov.base.saturating_add(iov.len) > 1 << 32)
+        {
+            warn!("Can't retry 32-bit ioctl with memory beyond 4 GiB");
+            return self.reply.error(EINVAL);
+        }
+        let out = fuse_ioctl_out {
+            result: 0,
+            flags: FUSE_IOCTL_RETRY,
+            in_iovs: in_iovs.len() as u32,
+            out_iovs: out_iovs.len() as u32,
+        };
+        let iovbytes = unsafe {
+            slice::from_raw_parts(
+                iovs.as_ptr() as *const u8,
+                iovs.len() * mem::size_of::<fuse_ioctl_iovec>(),
+            )
+        };
+        as_bytes(&out, |bytes| {
+            let mut sendbytes = bytes.to_vec();
+            sendbytes.push(iovbytes);
+            self.reply.send(0, &sendbytes);
+        });
+    }
+
+    /// Reply to a request with the given error code
+    pub fn error(self, err: c_int) {
+        self.reply.error(err);
+    }
+}
+
+///
+/// Poll Reply
+///
+#[derive(Debug)]
+pub struct ReplyPoll {
+    reply: ReplyRaw<fuse_poll_out>,
+}
+
+impl Reply for ReplyPoll {
+    fn new<S: ReplySender>(unique: u64, sender: S) -> ReplyPoll {
+        ReplyPoll {
+            reply: Reply::new(unique, sender),
+        }
+    }
+}
+
+impl ReplyPoll {
+    /// Reply to a request with the given ready events (POLLIN, POLLOUT, ...)
+    pub fn poll(self, revents: u32) {
+        self.reply.ok(&fuse_poll_out {
+            revents,
+            padding: 0,
+        });
     }
 
     /// Reply to a request with the given error code
@@ -620,15 +1090,15 @@ impl ReplyDirectory {
             return true;
         }
         unsafe {
-            let p = self.data.as_mut_ptr().offset(self.data.len() as isize);
+            let p = self.data.as_mut_ptr().add(self.data.len());
             let pdirent: *mut fuse_dirent = mem::transmute(p);
             (*pdirent).ino = ino;
             (*pdirent).off = offset as u64;
             (*pdirent).namelen = name.len() as u32;
             (*pdirent).typ = mode_from_kind_and_perm(kind, 0) >> 12;
-            let p = p.offset(mem::size_of_val(&*pdirent) as isize);
+            let p = p.add(mem::size_of_val(&*pdirent));
             ptr::copy_nonoverlapping(name.as_ptr(), p, name.len());
-            let p = p.offset(name.len() as isize);
+            let p = p.add(name.len());
             ptr::write_bytes(p, 0u8, padlen);
             let newlen = self.data.len() + entsize;
             self.data.set_len(newlen);
@@ -647,6 +1117,81 @@ impl ReplyDirectory {
     }
 }
 
+///
+/// Directory reply with entry attributes (readdirplus)
+///
+#[derive(Debug)]
+pub struct ReplyDirectoryPlus {
+    reply: ReplyRaw<()>,
+    data: Vec<u8>,
+}
+
+impl ReplyDirectoryPlus {
+    /// Creates a new ReplyDirectoryPlus with a specified buffer size.
+    pub fn new<S: ReplySender>(unique: u64, sender: S, size: usize) -> ReplyDirectoryPlus {
+        ReplyDirectoryPlus {
+            reply: Reply::new(unique, sender),
+            data: Vec::with_capacity(size),
+        }
+    }
+
+    /// Add an entry with its attributes to the directory reply buffer. Returns true if the
+    /// buffer is full (and the entry was not added). A transparent offset value can be
+    /// provided for each entry. The kernel uses these value to request the next entries in
+    /// further readdirplus calls. Like a reply to lookup, every added entry except "." and
+    /// ".." counts as a lookup of its inode, which the kernel will forget later.
+    pub fn add<T: AsRef<OsStr>>(
+        &mut self,
+        offset: i64,
+        name: T,
+        ttl: &Duration,
+        attr: &FileAttr,
+        generation: u64,
+    ) -> bool {
+        let name = name.as_ref().as_bytes();
+        let entlen = mem::size_of::<fuse_direntplus>() + name.len();
+        let entsize = (entlen + mem::size_of::<u64>() - 1) & !(mem::size_of::<u64>() - 1); // 64bit align
+        if self.data.len() + entsize > self.data.capacity() {
+            return true;
+        }
+        let entry = fuse_direntplus {
+            entry_out: fuse_entry_out {
+                nodeid: attr.ino,
+                generation,
+                entry_valid: ttl.as_secs(),
+                attr_valid: ttl.as_secs(),
+                entry_valid_nsec: ttl.subsec_nanos(),
+                attr_valid_nsec: ttl.subsec_nanos(),
+                attr: fuse_attr_from_attr(attr),
+            },
+            dirent: fuse_dirent {
+                ino: attr.ino,
+                off: offset as u64,
+                namelen: name.len() as u32,
+                typ: mode_from_kind_and_perm(attr.kind, 0) >> 12,
+            },
+        };
+        as_bytes(&entry, |bytes| {
+            for b in bytes {
+                self.data.extend_from_slice(b);
+            }
+        });
+        self.data.extend_from_slice(name);
+        self.data.resize(self.data.len() + entsize - entlen, 0);
+        false
+    }
+
+    /// Reply to a request with the filled directory buffer
+    pub fn ok(mut self) {
+        self.reply.send(0, &[&self.data]);
+    }
+
+    /// Reply to a request with the given error code
+    pub fn error(self, err: c_int) {
+        self.reply.error(err);
+    }
+}
+
 ///
 /// Xattr reply
 ///
@@ -666,10 +1211,7 @@ impl Reply for ReplyXattr {
 impl ReplyXattr {
     /// Reply to a request with the size of the xattr.
     pub fn size(self, size: u32) {
-        self.reply.ok(&fuse_getxattr_out {
-            size: size,
-            padding: 0,
-        });
+        self.reply.ok(&fuse_getxattr_out { size, padding: 0 });
     }
 
     /// Reply to a request with the data in the xattr.
@@ -686,13 +1228,20 @@ impl ReplyXattr {
 #[cfg(test)]
 mod test {
     use super::as_bytes;
+    use super::ReplyDirectoryPlus;
+    use super::ReplyLseek;
     #[cfg(target_os = "macos")]
     use super::ReplyXTimes;
     use super::ReplyXattr;
     use super::{Reply, ReplyAttr, ReplyData, ReplyEmpty, ReplyEntry, ReplyOpen, ReplyRaw};
     use super::{ReplyBmap, ReplyCreate, ReplyDirectory, ReplyLock, ReplyStatfs, ReplyWrite};
-    use crate::{FileAttr, FileType};
+    use super::{ReplyIoctl, ReplyPoll, ReplyStatx, VersionedReply, ZeroMessageOpen};
+    use crate::{FileAttr, FileType, OpenOptions};
+    use fuse_abi::consts::{FUSE_IOCTL_32BIT, FUSE_IOCTL_UNRESTRICTED};
+    use fuse_abi::fuse_ioctl_in;
+    use std::convert::TryInto;
     use std::sync::mpsc::{channel, Sender};
+    use std::sync::Arc;
     use std::thread;
     use std::time::{Duration, UNIX_EPOCH};
 
@@ -761,6 +1310,15 @@ mod test {
         }
     }
 
+    /// Since ABI 7.9, `fuse_attr` ends with the `blksize` and `padding` fields. Inserts them
+    /// (zeroed) into expected reply data that has `trailing` bytes following the attributes.
+    fn attr_padded(mut expected: Vec<Vec<u8>>, trailing: usize) -> Vec<Vec<u8>> {
+        expected[0][0] += 8;
+        let pos = expected[1].len() - trailing;
+        expected[1].splice(pos..pos, [0; 8].iter().cloned());
+        expected
+    }
+
     #[test]
     fn reply_raw() {
         let data = Data {
@@ -781,6 +1339,27 @@ mod test {
         reply.ok(&data);
     }
 
+    #[test]
+    fn reply_raw_with_data() {
+        let data = Data {
+            a: 0x12,
+            b: 0x34,
+            c: 0x5678,
+        };
+        let sender = AssertSender {
+            expected: vec![
+                vec![
+                    0x16, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xef, 0xbe, 0xad, 0xde, 0x00,
+                    0x00, 0x00, 0x00,
+                ],
+                vec![0x12, 0x34, 0x78, 0x56],
+                vec![0xde, 0xad],
+            ],
+        };
+        let reply: ReplyRaw<Data> = Reply::new(0xdeadbeef, sender);
+        reply.ok_with_data(&data, &[0xde, 0xad]);
+    }
+
     #[test]
     fn reply_error() {
         let sender = AssertSender {
@@ -822,8 +1401,8 @@ mod test {
 
     #[test]
     fn reply_entry() {
-        let sender = AssertSender {
-            expected: if cfg!(target_os = "macos") {
+        let expected = attr_padded(
+            if cfg!(target_os = "macos") {
                 vec![
                     vec![
                         0x98, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xef, 0xbe, 0xad, 0xde,
@@ -864,7 +1443,9 @@ mod test {
                     ],
                 ]
             },
-        };
+            0,
+        );
+        let sender = AssertSender { expected };
         let reply: ReplyEntry = Reply::new(0xdeadbeef, sender);
         let time = UNIX_EPOCH + Duration::new(0x1234, 0x5678);
         let ttl = Duration::new(0x8765, 0x4321);
@@ -889,8 +1470,8 @@ mod test {
 
     #[test]
     fn reply_attr() {
-        let sender = AssertSender {
-            expected: if cfg!(target_os = "macos") {
+        let expected = attr_padded(
+            if cfg!(target_os = "macos") {
                 vec![
                     vec![
                         0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xef, 0xbe, 0xad, 0xde,
@@ -927,7 +1508,9 @@ mod test {
                     ],
                 ]
             },
-        };
+            0,
+        );
+        let sender = AssertSender { expected };
         let reply: ReplyAttr = Reply::new(0xdeadbeef, sender);
         let time = UNIX_EPOCH + Duration::new(0x1234, 0x5678);
         let ttl = Duration::new(0x8765, 0x4321);
@@ -985,7 +1568,69 @@ mod test {
             ],
         };
         let reply: ReplyOpen = Reply::new(0xdeadbeef, sender);
-        reply.opened(0x1122, 0x33);
+        reply.opened(0x1122, OpenOptions::from_bits(0x33));
+    }
+
+    #[test]
+    fn reply_open_unsupported() {
+        let reply = |proto_minor, flags| {
+            versioned(proto_minor, |reply: ReplyOpen| reply.opened(0x11, flags))
+        };
+        let data = reply(31, OpenOptions::CACHE_DIR | OpenOptions::STREAM);
+        assert_eq!(data.len(), 32);
+        assert_eq!(&data[4..8], &0i32.to_ne_bytes());
+        let data = reply(31, OpenOptions::STREAM | OpenOptions::NOFLUSH);
+        assert_eq!(data.len(), 16);
+        assert_eq!(&data[4..8], &(-libc::EINVAL).to_ne_bytes());
+        let data = reply(40, OpenOptions::from_bits(1 << 20));
+        assert_eq!(&data[4..8], &(-libc::EINVAL).to_ne_bytes());
+    }
+
+    #[test]
+    fn reply_open_passthrough_without_backing() {
+        let data = versioned(40, |reply: ReplyOpen| {
+            reply.opened(0x11, OpenOptions::PASSTHROUGH)
+        });
+        assert_eq!(data.len(), 16);
+        assert_eq!(&data[4..8], &(-libc::EINVAL).to_ne_bytes());
+        let attr = FileAttr {
+            ino: 0x11,
+            size: 0,
+            blocks: 0,
+            atime: UNIX_EPOCH,
+            mtime: UNIX_EPOCH,
+            ctime: UNIX_EPOCH,
+            crtime: UNIX_EPOCH,
+            kind: FileType::RegularFile,
+            perm: 0o644,
+            nlink: 1,
+            uid: 0,
+            gid: 0,
+            rdev: 0,
+            flags: 0,
+        };
+        let data = versioned(40, |reply: ReplyCreate| {
+            let ttl = Duration::new(1, 0);
+            reply.created(&ttl, &attr, 0, 0x11, OpenOptions::PASSTHROUGH)
+        });
+        assert_eq!(data.len(), 16);
+        assert_eq!(&data[4..8], &(-libc::EINVAL).to_ne_bytes());
+    }
+
+    #[test]
+    fn reply_open_zero_message() {
+        let state = Arc::new(ZeroMessageOpen::default());
+        let reply = |err| {
+            versioned(40, |reply: ReplyOpen| reply.zero_message(&state).error(err));
+        };
+        // ENOSYS is an error unless zero-message opens are enabled
+        reply(libc::ENOSYS);
+        assert!(!state.is_active());
+        state.enable(true);
+        reply(libc::EIO);
+        assert!(!state.is_active());
+        reply(libc::ENOSYS);
+        assert!(state.is_active());
     }
 
     #[test]
@@ -1028,8 +1673,8 @@ mod test {
 
     #[test]
     fn reply_create() {
-        let sender = AssertSender {
-            expected: if cfg!(target_os = "macos") {
+        let expected = attr_padded(
+            if cfg!(target_os = "macos") {
                 vec![
                     vec![
                         0xa8, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xef, 0xbe, 0xad, 0xde,
@@ -1068,12 +1713,14 @@ mod test {
                         0x00, 0x00, 0x00, 0x00, 0x78, 0x56, 0x00, 0x00, 0x78, 0x56, 0x00, 0x00,
                         0x78, 0x56, 0x00, 0x00, 0xa4, 0x81, 0x00, 0x00, 0x55, 0x00, 0x00, 0x00,
                         0x66, 0x00, 0x00, 0x00, 0x77, 0x00, 0x00, 0x00, 0x88, 0x00, 0x00, 0x00,
-                        0xbb, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xcc, 0x00, 0x00, 0x00,
+                        0xbb, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x4c, 0x00, 0x00, 0x00,
                         0x00, 0x00, 0x00, 0x00,
                     ],
                 ]
             },
-        };
+            16,
+        );
+        let sender = AssertSender { expected };
         let reply: ReplyCreate = Reply::new(0xdeadbeef, sender);
         let time = UNIX_EPOCH + Duration::new(0x1234, 0x5678);
         let ttl = Duration::new(0x8765, 0x4321);
@@ -1093,7 +1740,122 @@ mod test {
             rdev: 0x88,
             flags: 0x99,
         };
-        reply.created(&ttl, &attr, 0xaa, 0xbb, 0xcc);
+        reply.created(&ttl, &attr, 0xaa, 0xbb, OpenOptions::from_bits(0x4c));
+    }
+
+    struct CaptureSender {
+        tx: Sender<Vec<u8>>,
+    }
+
+    impl super::ReplySender for CaptureSender {
+        fn send(&self, data: &[&[u8]]) {
+            self.tx.send(data.concat()).unwrap();
+        }
+    }
+
+    /// Send a reply with the given protocol version and return the sent data
+    fn versioned<T: VersionedReply, F: FnOnce(T)>(proto_minor: u32, f: F) -> Vec<u8> {
+        let (tx, rx) = channel();
+        let mut reply: T = Reply::new(0xdeadbeef, CaptureSender { tx });
+        reply.set_proto_minor(proto_minor);
+        f(reply);
+        rx.recv().unwrap()
+    }
+
+    #[test]
+    fn reply_attr_compat() {
+        let ttl = Duration::new(0x8765, 0x4321);
+        let attr = FileAttr {
+            ino: 0x11,
+            size: 0x22,
+            blocks: 0x33,
+            atime: UNIX_EPOCH,
+            mtime: UNIX_EPOCH,
+            ctime: UNIX_EPOCH,
+            crtime: UNIX_EPOCH,
+            kind: FileType::RegularFile,
+            perm: 0o644,
+            nlink: 0x55,
+            uid: 0x66,
+            gid: 0x77,
+            rdev: 0x88,
+            flags: 0x99,
+        };
+        // Kernels before ABI 7.9 expect attributes without the trailing blksize and padding
+        let compat = |mut data: Vec<u8>, trailing: usize| {
+            data[0] -= 8;
+            let pos = data.len() - trailing;
+            data.drain(pos - 8..pos);
+            data
+        };
+        let attr_reply =
+            |proto_minor| versioned(proto_minor, |reply: ReplyAttr| reply.attr(&ttl, &attr));
+        assert_eq!(attr_reply(8), compat(attr_reply(9), 0));
+        let entry_reply = |proto_minor| {
+            versioned(proto_minor, |reply: ReplyEntry| {
+                reply.entry(&ttl, &attr, 0xaa)
+            })
+        };
+        assert_eq!(entry_reply(8), compat(entry_reply(9), 0));
+        let create_reply = |proto_minor| {
+            versioned(proto_minor, |reply: ReplyCreate| {
+                reply.created(&ttl, &attr, 0xaa, 0xbb, OpenOptions::KEEP_CACHE)
+            })
+        };
+        assert_eq!(create_reply(8), compat(create_reply(9), 16));
+    }
+
+    #[test]
+    fn reply_statx() {
+        let attr = FileAttr {
+            ino: 0x11,
+            size: 0x22,
+            blocks: 0x33,
+            atime: UNIX_EPOCH + Duration::new(0x1234, 0x5678),
+            mtime: UNIX_EPOCH,
+            ctime: UNIX_EPOCH,
+            crtime: UNIX_EPOCH + Duration::new(0x4321, 0x8765),
+            kind: FileType::CharDevice,
+            perm: 0o600,
+            nlink: 0x55,
+            uid: 0x66,
+            gid: 0x77,
+            rdev: 0x0010_0823, // major 8, minor 0x123
+            flags: 0,
+        };
+        let (tx, rx) = channel();
+        let reply: ReplyStatx = Reply::new(0xdeadbeef, CaptureSender { tx });
+        reply.statx(&Duration::new(0x8765, 0x4321), &attr, 0x10, 0x30);
+        let data = rx.recv().unwrap();
+        let u32_at = |pos: usize| u32::from_ne_bytes(data[pos..pos + 4].try_into().unwrap());
+        let u64_at = |pos: usize| u64::from_ne_bytes(data[pos..pos + 8].try_into().unwrap());
+        assert_eq!(data.len(), 304);
+        assert_eq!(u32_at(0), 304);
+        assert_eq!(u64_at(16), 0x8765);
+        // statx starts after the header and attr_valid, attr_valid_nsec, flags and spare
+        let stat = 48;
+        assert_eq!(u32_at(stat), 0x0fff);
+        assert_eq!(u64_at(stat + 8), 0x10);
+        assert_eq!(u32_at(stat + 16), 0x55);
+        assert_eq!(u32_at(stat + 28) & 0xffff, 0o020600);
+        assert_eq!(u64_at(stat + 32), 0x11);
+        assert_eq!(u64_at(stat + 56), 0x30);
+        assert_eq!(u64_at(stat + 64), 0x1234);
+        assert_eq!(u32_at(stat + 72), 0x5678);
+        assert_eq!(u64_at(stat + 80), 0x4321);
+        assert_eq!(u32_at(stat + 88), 0x8765);
+        assert_eq!(u32_at(stat + 128), 8);
+        assert_eq!(u32_at(stat + 132), 0x123);
+    }
+
+    #[test]
+    fn sx_time_before_epoch() {
+        let time = super::sx_time_from_system_time(&(UNIX_EPOCH - Duration::new(2, 250)));
+        assert_eq!((time.tv_sec, time.tv_nsec), (-3, 999_999_750));
+        let time = super::sx_time_from_system_time(&(UNIX_EPOCH - Duration::new(2, 0)));
+        assert_eq!((time.tv_sec, time.tv_nsec), (-2, 0));
+        let time = super::sx_time_from_system_time(&(UNIX_EPOCH + Duration::new(2, 250)));
+        assert_eq!((time.tv_sec, time.tv_nsec), (2, 250));
     }
 
     #[test]
@@ -1129,6 +1891,129 @@ mod test {
         reply.bmap(0x1234);
     }
 
+    #[test]
+    fn reply_lseek() {
+        let sender = AssertSender {
+            expected: vec![
+                vec![
+                    0x18, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xef, 0xbe, 0xad, 0xde, 0x00,
+                    0x00, 0x00, 0x00,
+                ],
+                vec![0x00, 0x00, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00],
+            ],
+        };
+        let reply: ReplyLseek = Reply::new(0xdeadbeef, sender);
+        reply.offset(0x100000);
+    }
+
+    /// An ioctl request with the given flags
+    fn ioctl_in(flags: u32) -> fuse_ioctl_in {
+        fuse_ioctl_in {
+            fh: 0,
+            flags,
+            cmd: 0,
+            arg: 0,
+            in_size: 0,
+            out_size: 0x1000,
+        }
+    }
+
+    #[test]
+    fn reply_ioctl() {
+        let sender = AssertSender {
+            expected: vec![
+                vec![
+                    0x22, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xef, 0xbe, 0xad, 0xde, 0x00,
+                    0x00, 0x00, 0x00,
+                ],
+                vec![
+                    0x2a, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+                    0x00, 0x00, 0x00,
+                ],
+                vec![0xde, 0xad],
+            ],
+        };
+        let reply: ReplyIoctl = Reply::new(0xdeadbeef, sender);
+        reply.ioctl(42, &[0xde, 0xad]);
+    }
+
+    #[test]
+    fn reply_ioctl_too_large() {
+        let sender = AssertSender {
+            expected: vec![vec![
+                0x10, 0x00, 0x00, 0x00, 0xfb, 0xff, 0xff, 0xff, 0xef, 0xbe, 0xad, 0xde, 0x00, 0x00,
+                0x00, 0x00,
+            ]],
+        };
+        let arg = fuse_ioctl_in {
+            out_size: 1,
+            ..ioctl_in(0)
+        };
+        let reply: ReplyIoctl = Reply::new(0xdeadbeef, sender);
+        reply.request(&arg).ioctl(42, &[0xde, 0xad]);
+    }
+
+    #[test]
+    fn reply_ioctl_retry() {
+        let sender = AssertSender {
+            expected: vec![
+                vec![
+                    0x40, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xef, 0xbe, 0xad, 0xde, 0x00,
+                    0x00, 0x00, 0x00,
+                ],
+                vec![
+                    0x00, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01,
+                    0x00, 0x00, 0x00,
+                ],
+                vec![
+                    0x00, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00, 0x00,
+                    0x00, 0x00, 0x00, 0x00, 0x20, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x00,
+                    0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+                ],
+            ],
+        };
+        let reply: ReplyIoctl = Reply::new(0xdeadbeef, sender);
+        let reply = reply.request(&ioctl_in(FUSE_IOCTL_UNRESTRICTED));
+        reply.retry(&[(0x1000, 8)], &[(0x2000, 16)]);
+    }
+
+    #[test]
+    fn reply_ioctl_retry_invalid() {
+        let sender = AssertSender {
+            expected: vec![vec![
+                0x10, 0x00, 0x00, 0x00, 0xfb, 0xff, 0xff, 0xff, 0xef, 0xbe, 0xad, 0xde, 0x00, 0x00,
+                0x00, 0x00,
+            ]],
+        };
+        let reply: ReplyIoctl = Reply::new(0xdeadbeef, sender);
+        reply.retry(&[(0x1000, 8)], &[]);
+        let sender = AssertSender {
+            expected: vec![vec![
+                0x10, 0x00, 0x00, 0x00, 0xea, 0xff, 0xff, 0xff, 0xef, 0xbe, 0xad, 0xde, 0x00, 0x00,
+                0x00, 0x00,
+            ]],
+        };
+        let flags = FUSE_IOCTL_UNRESTRICTED | FUSE_IOCTL_32BIT;
+        let reply: ReplyIoctl = Reply::new(0xdeadbeef, sender);
+        let reply = reply.request(&ioctl_in(flags));
+        reply.retry(&[(0xffff_f000, 0x2000)], &[]);
+    }
+
+    #[test]
+    fn reply_poll() {
+        let sender = AssertSender {
+            expected: vec![
+                vec![
+                    0x18, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xef, 0xbe, 0xad, 0xde, 0x00,
+                    0x00, 0x00, 0x00,
+                ],
+                vec![0x05, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+            ],
+        };
+        let reply: ReplyPoll = Reply::new(0xdeadbeef, sender);
+        reply.poll(0x05);
+    }
+
     #[test]
     fn reply_directory() {
         let sender = AssertSender {
@@ -1152,6 +2037,56 @@ mod test {
         reply.ok();
     }
 
+    #[test]
+    #[cfg(not(target_os = "macos"))]
+    fn reply_directory_plus() {
+        let expected = attr_padded(
+            vec![
+                vec![
+                    0xa8, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xef, 0xbe, 0xad, 0xde, 0x00,
+                    0x00, 0x00, 0x00,
+                ],
+                vec![
+                    0x11, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xaa, 0x00, 0x00, 0x00, 0x00,
+                    0x00, 0x00, 0x00, 0x65, 0x87, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x65, 0x87,
+                    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x21, 0x43, 0x00, 0x00, 0x21, 0x43, 0x00,
+                    0x00, 0x11, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x22, 0x00, 0x00, 0x00,
+                    0x00, 0x00, 0x00, 0x00, 0x33, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x34,
+                    0x12, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x34, 0x12, 0x00, 0x00, 0x00, 0x00,
+                    0x00, 0x00, 0x34, 0x12, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x78, 0x56, 0x00,
+                    0x00, 0x78, 0x56, 0x00, 0x00, 0x78, 0x56, 0x00, 0x00, 0xa4, 0x81, 0x00, 0x00,
+                    0x55, 0x00, 0x00, 0x00, 0x66, 0x00, 0x00, 0x00, 0x77, 0x00, 0x00, 0x00, 0x88,
+                    0x00, 0x00, 0x00, 0x11, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00,
+                    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x05, 0x00, 0x00, 0x00, 0x08, 0x00, 0x00,
+                    0x00, 0x68, 0x65, 0x6c, 0x6c, 0x6f, 0x00, 0x00, 0x00,
+                ],
+            ],
+            32,
+        );
+        let sender = AssertSender { expected };
+        let mut reply = ReplyDirectoryPlus::new(0xdeadbeef, sender, 4096);
+        let time = UNIX_EPOCH + Duration::new(0x1234, 0x5678);
+        let ttl = Duration::new(0x8765, 0x4321);
+        let attr = FileAttr {
+            ino: 0x11,
+            size: 0x22,
+            blocks: 0x33,
+            atime: time,
+            mtime: time,
+            ctime: time,
+            crtime: time,
+            kind: FileType::RegularFile,
+            perm: 0o644,
+            nlink: 0x55,
+            uid: 0x66,
+            gid: 0x77,
+            rdev: 0x88,
+            flags: 0x99,
+        };
+        assert!(!reply.add(1, "hello", &ttl, &attr, 0xaa));
+        reply.ok();
+    }
+
     impl super::ReplySender for Sender<()> {
         fn send(&self, _: &[&[u8]]) {
             Sender::send(self, ()).unwrap()
@@ -1185,7 +2120,7 @@ mod test {
             ],
         };
         let reply = ReplyXattr::new(0xdeadbeef, sender);
-        reply.data(&vec![0x11, 0x22, 0x33, 0x44]);
+        reply.data(&[0x11, 0x22, 0x33, 0x44]);
     }
 
     #[test]
@@ -7,30 +7,46 @@
 
 use fuse_abi::consts::*;
 use fuse_abi::*;
-use libc::{EIO, ENOSYS, EPROTO};
+use libc::{EAGAIN, EINVAL, EIO, ENOSYS, EPROTO};
 use log::{debug, error, warn};
 use std::convert::TryFrom;
+use std::ffi::OsString;
+use std::io;
+use std::os::unix::io::AsRawFd;
 use std::path::Path;
+use std::ptr;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use crate::channel::ChannelSender;
-use crate::ll;
-use crate::reply::{Reply, ReplyDirectory, ReplyEmpty, ReplyRaw};
+use crate::cuse::{CharDevice, CuseSession};
+use crate::interrupt::{Interrupt, Interrupts};
+use crate::ll::{self, RequestError};
+use crate::notify::PollHandle;
+use crate::passthrough::BackingId;
+use crate::reply::VersionedReply;
+use crate::reply::{Reply, ReplyDirectory, ReplyDirectoryPlus, ReplyEmpty, ReplyIoctl, ReplyRaw};
 use crate::session::{Session, MAX_WRITE_SIZE};
-use crate::Filesystem;
+use crate::FallocateFlags;
+use crate::ReplyOpen;
+use crate::{Filesystem, KernelConfig, TimeOrNow};
 
-/// We generally support async reads
-#[cfg(not(target_os = "macos"))]
-const INIT_FLAGS: u32 = FUSE_ASYNC_READ;
-// TODO: Add FUSE_EXPORT_SUPPORT and FUSE_BIG_WRITES (requires ABI 7.10)
-
-/// On macOS, we additionally support case insensitiveness, volume renames and xtimes
-/// TODO: we should eventually let the filesystem implementation decide which flags to set
-#[cfg(target_os = "macos")]
-const INIT_FLAGS: u32 = FUSE_ASYNC_READ | FUSE_CASE_INSENSITIVE | FUSE_VOL_RENAME | FUSE_XTIMES;
-// TODO: Add FUSE_EXPORT_SUPPORT and FUSE_BIG_WRITES (requires ABI 7.10)
+/// Reply to a request that couldn't be parsed with an error matching the reason, unless its
+/// header is unreadable or its operation doesn't expect a reply
+fn reply_invalid(ch: ChannelSender, data: &[u8], err: &RequestError) {
+    let errno = match err {
+        RequestError::ShortReadHeader(_) => return,
+        RequestError::UnknownOperation(_) => ENOSYS,
+        RequestError::ShortRead(..) => EIO,
+        RequestError::InsufficientData => EINVAL,
+    };
+    let header: fuse_in_header = unsafe { ptr::read_unaligned(data.as_ptr() as *const _) };
+    match fuse_opcode::try_from(header.opcode) {
+        Ok(fuse_opcode::FUSE_FORGET) | Ok(fuse_opcode::FUSE_BATCH_FORGET) => (),
+        _ => ReplyEmpty::new(header.unique, ch).error(errno),
+    }
+}
 
 /// Request data structure
 #[derive(Debug)]
@@ -39,21 +55,66 @@ pub struct Request {
     ch: ChannelSender,
     /// Parsed request
     request: ll::Request,
+    /// Interruption state
+    interrupt: Arc<Interrupt>,
+    /// FUSE protocol minor version the request was sent with
+    proto_minor: u32,
 }
 
 impl Request {
-    /// Create a new request from the given data
-    pub fn new(ch: ChannelSender, data: &[u8]) -> Option<Request> {
-        let request = match ll::Request::try_from(data) {
+    /// Create a new request from the given data, sent by a kernel driver that uses the
+    /// given minor version of the FUSE protocol (and the extended setxattr arguments, if
+    /// negotiated). Requests that can't be parsed are replied with an error (if possible)
+    /// and `None` is returned.
+    pub fn new(
+        ch: ChannelSender,
+        data: &[u8],
+        proto_minor: u32,
+        setxattr_ext: bool,
+    ) -> Option<Request> {
+        let request = match ll::Request::parse(data, proto_minor, setxattr_ext) {
             Ok(request) => request,
             Err(err) => {
-                // FIXME: Reply with ENOSYS?
                 error!("{}", err);
+                reply_invalid(ch, data, &err);
                 return None;
             }
         };
 
-        Some(Self { ch, request })
+        Some(Self {
+            ch,
+            request,
+            interrupt: Arc::new(Interrupt::default()),
+            proto_minor,
+        })
+    }
+
+    /// Returns true if the kernel may interrupt this request. Interrupts, forgets and
+    /// replies to retrieve notifications are never interrupted. The latter carry the id of
+    /// the notification as unique, which may be equal to the unique of a request in flight.
+    pub fn interruptible(&self) -> bool {
+        !matches!(
+            self.request.operation(),
+            ll::Operation::Interrupt { .. }
+                | ll::Operation::Forget { .. }
+                | ll::Operation::BatchForget { .. }
+                | ll::Operation::NotifyReply { .. }
+        )
+    }
+
+    /// Register this request as being in flight, so that a later FUSE_INTERRUPT can find it.
+    /// Must be called by the session loop before the request is dispatched. Requests that
+    /// aren't interruptible aren't registered and must not be unregistered either.
+    pub fn register(&mut self, interrupts: &Interrupts) {
+        if !self.interruptible() {
+            return;
+        }
+        let (interrupt, stale) = interrupts.register(self.request.unique());
+        self.interrupt = interrupt;
+        // Let the kernel requeue an interrupt whose request hasn't shown up
+        if let Some(unique) = stale {
+            ReplyEmpty::new(unique, self.ch.clone()).error(EAGAIN);
+        }
     }
 
     /// Dispatch request to the given filesystem.
@@ -73,12 +134,16 @@ impl Request {
                     reply.error(EPROTO);
                     return;
                 }
-                // Remember ABI version supported by kernel
+                // Remember ABI version supported by kernel. Requests and replies use the
+                // layout of the older version of both sides.
                 se.proto_major.store(arg.major, Ordering::Relaxed);
-                se.proto_minor.store(arg.minor, Ordering::Relaxed);
+                se.proto_minor
+                    .store(arg.minor.min(FUSE_KERNEL_MINOR_VERSION), Ordering::Relaxed);
 
                 // Call filesystem init method and give it a chance to return an error
-                let res = se.filesystem.init(req).await;
+                // or to change the configuration of the connection
+                let mut config = KernelConfig::new(arg);
+                let res = se.filesystem.init(req, &mut config).await;
                 if let Err(err) = res {
                     reply.error(err);
                     return;
@@ -86,36 +151,38 @@ impl Request {
                 // Reply with our desired version and settings. If the kernel supports a
                 // larger major version, it'll re-send a matching init message. If it
                 // supports only lower major versions, we replied with an error above.
+                let (flags, flags2) = config.reply_flags();
+                #[cfg(not(target_os = "macos"))]
+                se.setxattr_ext
+                    .store(flags as u64 & FUSE_SETXATTR_EXT != 0, Ordering::Relaxed);
+                se.no_open.enable(flags as u64 & FUSE_NO_OPEN_SUPPORT != 0);
+                se.no_opendir
+                    .enable(flags as u64 & FUSE_NO_OPENDIR_SUPPORT != 0);
                 let init = fuse_init_out {
                     major: FUSE_KERNEL_VERSION,
                     minor: FUSE_KERNEL_MINOR_VERSION,
-                    max_readahead: arg.max_readahead, // accept any readahead size
-                    flags: arg.flags & INIT_FLAGS, // use features given in INIT_FLAGS and reported as capable
-                    #[cfg(not(feature = "abi-7-13"))]
-                    unused: 0,
-                    max_write: MAX_WRITE_SIZE as u32, // use a max write size that fits into the session's buffer
-
-                    // Maximum number of pending "background" requests. A background request is any type of request for which the total number is not limited by other means. As of kernel 4.8, only two types of requests fall into this category:
-
-                    // Read-ahead requests
-                    // Asynchronous direct I/O requests
-                    // Read-ahead requests are generated (if max_readahead is non-zero) by the kernel to preemptively fill its caches when it anticipates that userspace will soon read more data.
-
-                    // Asynchronous direct I/O requests are generated if FUSE_CAP_ASYNC_DIO is enabled and userspace submits a large direct I/O request. In this case the kernel will internally split it up into multiple smaller requests and submit them to the filesystem concurrently.
-
-                    // Note that the following requests are not background requests: writeback requests (limited by the kernel's flusher algorithm), regular (i.e., synchronous and buffered) userspace read/write requests (limited to one per thread), asynchronous read requests (Linux's io_submit(2) call actually blocks, so these are also limited to one per thread).
-                    #[cfg(feature = "abi-7-13")]
-                    max_background: 32,
-
-                    // Kernel congestion threshold parameter. If the number of pending background requests exceeds this number, the FUSE kernel module will mark the filesystem as "congested". This instructs the kernel to expect that queued requests will take some time to complete, and to adjust its algorithms accordingly (e.g. by putting a waiting thread to sleep instead of using a busy-loop).
-                    #[cfg(feature = "abi-7-13")]
-                    congestion_threshold: 30,
+                    max_readahead: config.max_readahead(),
+                    flags,
+                    max_write: config.max_write(),
+                    max_background: config.max_background(),
+                    congestion_threshold: config.congestion_threshold(),
+                    time_gran: config.time_gran(),
+                    max_pages: config.max_pages(),
+                    map_alignment: config.map_alignment(),
+                    flags2,
+                    max_stack_depth: config.max_stack_depth(),
+                    unused: [0; 6],
                 };
                 debug!(
                     "INIT response: ABI {}.{}, flags {:#x}, max readahead {}, max write {}",
                     init.major, init.minor, init.flags, init.max_readahead, init.max_write
                 );
                 se.initialized.store(true, Ordering::Relaxed);
+                // Kernels before ABI 7.23 reject replies larger than their init_out
+                if arg.minor < 23 {
+                    reply.ok_truncated(&init, FUSE_COMPAT_22_INIT_OUT_SIZE);
+                    return;
+                }
                 reply.ok(&init);
             }
             // Any operation is invalid before initialization
@@ -135,14 +202,13 @@ impl Request {
                 req.reply::<ReplyEmpty>().error(EIO);
             }
 
-            ll::Operation::Interrupt { .. } => {
-                // TODO: handle FUSE_INTERRUPT
-                req.reply::<ReplyEmpty>().error(ENOSYS);
+            ll::Operation::Interrupt { arg } => {
+                se.interrupts.interrupt(arg.unique, req.request.unique()); // no reply
             }
 
             ll::Operation::Lookup { name } => {
                 se.filesystem
-                    .lookup(req, req.request.nodeid(), &name, req.reply())
+                    .lookup(req, req.request.nodeid(), name, req.versioned_reply())
                     .await;
             }
             ll::Operation::Forget { arg } => {
@@ -152,7 +218,7 @@ impl Request {
             }
             ll::Operation::GetAttr => {
                 se.filesystem
-                    .getattr(req, req.request.nodeid(), req.reply())
+                    .getattr(req, req.request.nodeid(), req.versioned_reply())
                     .await;
             }
             ll::Operation::SetAttr { arg } => {
@@ -172,13 +238,23 @@ impl Request {
                     0 => None,
                     _ => Some(arg.size),
                 };
-                let atime = match arg.valid & FATTR_ATIME {
+                let atime = match arg.valid & (FATTR_ATIME | FATTR_ATIME_NOW) {
+                    0 => None,
+                    _ if arg.valid & FATTR_ATIME_NOW != 0 => Some(TimeOrNow::Now),
+                    _ => Some(TimeOrNow::SpecificTime(
+                        UNIX_EPOCH + Duration::new(arg.atime, arg.atimensec),
+                    )),
+                };
+                let mtime = match arg.valid & (FATTR_MTIME | FATTR_MTIME_NOW) {
                     0 => None,
-                    _ => Some(UNIX_EPOCH + Duration::new(arg.atime, arg.atimensec)),
+                    _ if arg.valid & FATTR_MTIME_NOW != 0 => Some(TimeOrNow::Now),
+                    _ => Some(TimeOrNow::SpecificTime(
+                        UNIX_EPOCH + Duration::new(arg.mtime, arg.mtimensec),
+                    )),
                 };
-                let mtime = match arg.valid & FATTR_MTIME {
+                let ctime = match arg.valid & FATTR_CTIME {
                     0 => None,
-                    _ => Some(UNIX_EPOCH + Duration::new(arg.mtime, arg.mtimensec)),
+                    _ => Some(UNIX_EPOCH + Duration::new(arg.ctime, arg.ctimensec)),
                 };
                 let fh = match arg.valid & FATTR_FH {
                     0 => None,
@@ -235,12 +311,13 @@ impl Request {
                         size,
                         atime,
                         mtime,
+                        ctime,
                         fh,
                         crtime,
                         chgtime,
                         bkuptime,
                         flags,
-                        req.reply(),
+                        req.versioned_reply(),
                     )
                     .await;
             }
@@ -254,26 +331,32 @@ impl Request {
                     .mknod(
                         req,
                         req.request.nodeid(),
-                        &name,
+                        name,
                         arg.mode,
                         arg.rdev,
-                        req.reply(),
+                        req.versioned_reply(),
                     )
                     .await;
             }
             ll::Operation::MkDir { arg, name } => {
                 se.filesystem
-                    .mkdir(req, req.request.nodeid(), &name, arg.mode, req.reply())
+                    .mkdir(
+                        req,
+                        req.request.nodeid(),
+                        name,
+                        arg.mode,
+                        req.versioned_reply(),
+                    )
                     .await;
             }
             ll::Operation::Unlink { name } => {
                 se.filesystem
-                    .unlink(req, req.request.nodeid(), &name, req.reply())
+                    .unlink(req, req.request.nodeid(), name, req.reply())
                     .await;
             }
             ll::Operation::RmDir { name } => {
                 se.filesystem
-                    .rmdir(req, req.request.nodeid(), &name, req.reply())
+                    .rmdir(req, req.request.nodeid(), name, req.reply())
                     .await;
             }
             ll::Operation::SymLink { name, link } => {
@@ -281,9 +364,9 @@ impl Request {
                     .symlink(
                         req,
                         req.request.nodeid(),
-                        &name,
-                        &Path::new(link),
-                        req.reply(),
+                        name,
+                        Path::new(link),
+                        req.versioned_reply(),
                     )
                     .await;
             }
@@ -292,21 +375,51 @@ impl Request {
                     .rename(
                         req,
                         req.request.nodeid(),
-                        &name,
+                        name,
                         arg.newdir,
-                        &newname,
+                        newname,
+                        0,
+                        req.reply(),
+                    )
+                    .await;
+            }
+            ll::Operation::Rename2 { arg, name, newname } => {
+                se.filesystem
+                    .rename(
+                        req,
+                        req.request.nodeid(),
+                        name,
+                        arg.newdir,
+                        newname,
+                        arg.flags,
                         req.reply(),
                     )
                     .await;
             }
             ll::Operation::Link { arg, name } => {
                 se.filesystem
-                    .link(req, arg.oldnodeid, req.request.nodeid(), &name, req.reply())
+                    .link(
+                        req,
+                        arg.oldnodeid,
+                        req.request.nodeid(),
+                        name,
+                        req.versioned_reply(),
+                    )
                     .await;
             }
+            // Once the filesystem replied ENOSYS with zero-message opens, every open succeeds
+            ll::Operation::Open { .. } if se.no_open.is_active() => {
+                req.reply::<ReplyEmpty>().error(ENOSYS);
+            }
             ll::Operation::Open { arg } => {
+                let reply: ReplyOpen = req.versioned_reply();
                 se.filesystem
-                    .open(req, req.request.nodeid(), arg.flags, req.reply())
+                    .open(
+                        req,
+                        req.request.nodeid(),
+                        arg.flags,
+                        reply.zero_message(&se.no_open),
+                    )
                     .await;
             }
             ll::Operation::Read { arg } => {
@@ -322,7 +435,6 @@ impl Request {
                     .await;
             }
             ll::Operation::Write { arg, data } => {
-                assert!(data.len() == arg.size as usize);
                 se.filesystem
                     .write(
                         req,
@@ -347,10 +459,7 @@ impl Request {
                     .await;
             }
             ll::Operation::Release { arg } => {
-                let flush = match arg.release_flags & FUSE_RELEASE_FLUSH {
-                    0 => false,
-                    _ => true,
-                };
+                let flush = arg.release_flags & FUSE_RELEASE_FLUSH != 0;
                 se.filesystem
                     .release(
                         req,
@@ -364,17 +473,23 @@ impl Request {
                     .await;
             }
             ll::Operation::FSync { arg } => {
-                let datasync = match arg.fsync_flags & 1 {
-                    0 => false,
-                    _ => true,
-                };
+                let datasync = arg.fsync_flags & 1 != 0;
                 se.filesystem
                     .fsync(req, req.request.nodeid(), arg.fh, datasync, req.reply())
                     .await;
             }
+            ll::Operation::OpenDir { .. } if se.no_opendir.is_active() => {
+                req.reply::<ReplyEmpty>().error(ENOSYS);
+            }
             ll::Operation::OpenDir { arg } => {
+                let reply: ReplyOpen = req.versioned_reply();
                 se.filesystem
-                    .opendir(req, req.request.nodeid(), arg.flags, req.reply())
+                    .opendir(
+                        req,
+                        req.request.nodeid(),
+                        arg.flags,
+                        reply.zero_message(&se.no_opendir),
+                    )
                     .await;
             }
             ll::Operation::ReadDir { arg } => {
@@ -384,7 +499,26 @@ impl Request {
                         req.request.nodeid(),
                         arg.fh,
                         arg.offset as i64,
-                        ReplyDirectory::new(req.request.unique(), req.ch, arg.size as usize),
+                        ReplyDirectory::new(
+                            req.request.unique(),
+                            req.ch.clone(),
+                            arg.size as usize,
+                        ),
+                    )
+                    .await;
+            }
+            ll::Operation::ReadDirPlus { arg } => {
+                se.filesystem
+                    .readdirplus(
+                        req,
+                        req.request.nodeid(),
+                        arg.fh,
+                        arg.offset as i64,
+                        ReplyDirectoryPlus::new(
+                            req.request.unique(),
+                            req.ch.clone(),
+                            arg.size as usize,
+                        ),
                     )
                     .await;
             }
@@ -394,10 +528,7 @@ impl Request {
                     .await;
             }
             ll::Operation::FSyncDir { arg } => {
-                let datasync = match arg.fsync_flags & 1 {
-                    0 => false,
-                    _ => true,
-                };
+                let datasync = arg.fsync_flags & 1 != 0;
                 se.filesystem
                     .fsyncdir(req, req.request.nodeid(), arg.fh, datasync, req.reply())
                     .await;
@@ -408,7 +539,6 @@ impl Request {
                     .await;
             }
             ll::Operation::SetXAttr { arg, name, value } => {
-                assert!(value.len() == arg.size as usize);
                 #[cfg(target_os = "macos")]
                 #[inline]
                 fn get_position(arg: &fuse_setxattr_in) -> u32 {
@@ -419,6 +549,16 @@ impl Request {
                 fn get_position(_arg: &fuse_setxattr_in) -> u32 {
                     0
                 }
+                #[cfg(target_os = "macos")]
+                #[inline]
+                fn get_setxattr_flags(_arg: &fuse_setxattr_in) -> u32 {
+                    0
+                }
+                #[cfg(not(target_os = "macos"))]
+                #[inline]
+                fn get_setxattr_flags(arg: &fuse_setxattr_in) -> u32 {
+                    arg.setxattr_flags
+                }
                 se.filesystem
                     .setxattr(
                         req,
@@ -426,6 +566,7 @@ impl Request {
                         name,
                         value,
                         arg.flags,
+                        get_setxattr_flags(arg),
                         get_position(arg),
                         req.reply(),
                     )
@@ -456,10 +597,10 @@ impl Request {
                     .create(
                         req,
                         req.request.nodeid(),
-                        &name,
+                        name,
                         arg.mode,
                         arg.flags,
-                        req.reply(),
+                        req.versioned_reply(),
                     )
                     .await;
             }
@@ -521,32 +662,120 @@ impl Request {
                     )
                     .await;
             }
-            #[cfg(feature = "abi-7-11")]
-            ll::Operation::IoCtl { .. } => {
-                let reply: ReplyRaw<fuse_init_out> = req.reply();
-                reply.error(libc::ENOSYS)
+            ll::Operation::IoCtl { arg, data } => {
+                se.filesystem
+                    .ioctl(
+                        req,
+                        req.request.nodeid(),
+                        arg.fh,
+                        arg.flags,
+                        arg.cmd,
+                        arg.arg,
+                        data,
+                        arg.out_size,
+                        req.reply::<ReplyIoctl>().request(arg),
+                    )
+                    .await;
             }
-            #[cfg(feature = "abi-7-11")]
-            ll::Operation::Poll { .. } => {
-                let reply: ReplyRaw<fuse_init_out> = req.reply();
-                reply.error(libc::ENOSYS)
+            ll::Operation::Poll { arg } => {
+                let ph = match arg.flags & FUSE_POLL_SCHEDULE_NOTIFY {
+                    0 => None,
+                    _ => Some(PollHandle::new(arg.kh, req.ch.clone())),
+                };
+                se.filesystem
+                    .poll(
+                        req,
+                        req.request.nodeid(),
+                        arg.fh,
+                        ph,
+                        arg.flags,
+                        req.reply(),
+                    )
+                    .await;
             }
-            #[cfg(feature = "abi-7-15")]
-            ll::Operation::NotifyReply { .. } => {
-                let reply: ReplyRaw<fuse_init_out> = req.reply();
-                reply.error(libc::ENOSYS)
+            ll::Operation::NotifyReply { data, .. } => {
+                // The unique id of the request is the one of the retrieve notification
+                if !se.retrieves.complete(req.request.unique(), data.clone()) {
+                    warn!("Ignoring unexpected notify reply: {}", req.request);
+                } // no reply
             }
-            #[cfg(feature = "abi-7-16")]
-            ll::Operation::BatchForget { .. } => {
-                let reply: ReplyRaw<fuse_init_out> = req.reply();
-                reply.error(libc::ENOSYS)
+            ll::Operation::BatchForget { nodes, .. } => {
+                let nodes: Vec<_> = nodes.iter().map(|n| (n.nodeid, n.nlookup)).collect();
+                se.filesystem.batch_forget(req, &nodes).await; // no reply
             }
-            #[cfg(feature = "abi-7-19")]
-            ll::Operation::FAllocate { .. } => {
-                let reply: ReplyRaw<fuse_init_out> = req.reply();
-                reply.error(libc::ENOSYS)
+            ll::Operation::FAllocate { arg } => {
+                se.filesystem
+                    .fallocate(
+                        req,
+                        req.request.nodeid(),
+                        arg.fh,
+                        arg.offset,
+                        arg.length,
+                        FallocateFlags::from_bits(arg.mode),
+                        req.reply(),
+                    )
+                    .await;
+            }
+            ll::Operation::Lseek { arg } => {
+                se.filesystem
+                    .lseek(
+                        req,
+                        req.request.nodeid(),
+                        arg.fh,
+                        arg.offset as i64,
+                        arg.whence as i32,
+                        req.reply(),
+                    )
+                    .await;
+            }
+            ll::Operation::CopyFileRange { arg } => {
+                se.filesystem
+                    .copy_file_range(
+                        req,
+                        req.request.nodeid(),
+                        arg.fh_in,
+                        arg.off_in as i64,
+                        arg.nodeid_out,
+                        arg.fh_out,
+                        arg.off_out as i64,
+                        arg.len,
+                        arg.flags,
+                        req.reply(),
+                    )
+                    .await;
+            }
+            ll::Operation::SyncFs => {
+                se.filesystem
+                    .syncfs(req, req.request.nodeid(), req.reply())
+                    .await;
+            }
+            ll::Operation::TmpFile { arg } => {
+                se.filesystem
+                    .tmpfile(
+                        req,
+                        req.request.nodeid(),
+                        arg.mode,
+                        arg.flags,
+                        req.versioned_reply(),
+                    )
+                    .await;
+            }
+            ll::Operation::Statx { arg } => {
+                let fh = match arg.getattr_flags & FUSE_GETATTR_FH {
+                    0 => None,
+                    _ => Some(arg.fh),
+                };
+                se.filesystem
+                    .statx(
+                        req,
+                        req.request.nodeid(),
+                        fh,
+                        arg.sx_flags,
+                        arg.sx_mask,
+                        req.reply(),
+                    )
+                    .await;
             }
-            #[cfg(feature = "abi-7-12")]
             ll::Operation::CuseInit { .. } => {
                 let reply: ReplyRaw<fuse_init_out> = req.reply();
                 reply.error(libc::ENOSYS)
@@ -582,10 +811,133 @@ impl Request {
         }
     }
 
+    /// Dispatch request to the given character device.
+    /// This calls the appropriate device operation method for the
+    /// request and sends back the returned reply to the kernel
+    pub async fn dispatch_cuse<D: CharDevice + Send + Sync + 'static>(
+        self,
+        se: Arc<CuseSession<D>>,
+    ) {
+        let req = &self;
+        debug!("{}", req.request);
+
+        match req.request.operation() {
+            // Device initialization
+            ll::Operation::CuseInit { arg } => {
+                let reply: ReplyRaw<cuse_init_out> = req.reply();
+                // CUSE needs ABI version 7.11 or later
+                if arg.major != 7 || arg.minor < 11 {
+                    error!("Unsupported CUSE ABI version {}.{}", arg.major, arg.minor);
+                    reply.error(EPROTO);
+                    return;
+                }
+                // Call device init method and give it a chance to return an error
+                if let Err(err) = se.device.init(req).await {
+                    reply.error(err);
+                    return;
+                }
+                let flags = match se.unrestricted_ioctl {
+                    true => CUSE_UNRESTRICTED_IOCTL,
+                    false => 0,
+                };
+                let init = cuse_init_out {
+                    major: FUSE_KERNEL_VERSION,
+                    minor: FUSE_KERNEL_MINOR_VERSION,
+                    unused: 0,
+                    flags: arg.flags & flags,
+                    max_read: MAX_WRITE_SIZE as u32,
+                    max_write: MAX_WRITE_SIZE as u32,
+                    dev_major: se.dev_major,
+                    dev_minor: se.dev_minor,
+                    spare: [0; 10],
+                };
+                debug!(
+                    "CUSE_INIT response: ABI {}.{}, flags {:#x}, device {}:{}",
+                    init.major, init.minor, init.flags, init.dev_major, init.dev_minor
+                );
+                se.proto_major.store(arg.major, Ordering::Relaxed);
+                se.proto_minor
+                    .store(arg.minor.min(FUSE_KERNEL_MINOR_VERSION), Ordering::Relaxed);
+                se.initialized.store(true, Ordering::Relaxed);
+                reply.ok_with_data(&init, se.devinfo());
+            }
+            // Any operation is invalid before initialization
+            _ if !se.initialized.load(Ordering::Relaxed) => {
+                warn!("Ignoring CUSE operation before init: {}", req.request);
+                req.reply::<ReplyEmpty>().error(EIO);
+            }
+
+            ll::Operation::Interrupt { arg } => {
+                se.interrupts.interrupt(arg.unique, req.request.unique()); // no reply
+            }
+
+            ll::Operation::Open { arg } => {
+                se.device.open(req, arg.flags, req.versioned_reply()).await;
+            }
+            ll::Operation::Read { arg } => {
+                se.device
+                    .read(req, arg.fh, arg.offset as i64, arg.size, req.reply())
+                    .await;
+            }
+            ll::Operation::Write { arg, data } => {
+                se.device
+                    .write(
+                        req,
+                        arg.fh,
+                        arg.offset as i64,
+                        data,
+                        arg.write_flags,
+                        req.reply(),
+                    )
+                    .await;
+            }
+            ll::Operation::Release { arg } => {
+                se.device.release(req, arg.fh, arg.flags, req.reply()).await;
+            }
+            ll::Operation::IoCtl { arg, data } => {
+                se.device
+                    .ioctl(
+                        req,
+                        arg.fh,
+                        arg.flags,
+                        arg.cmd,
+                        arg.arg,
+                        data,
+                        arg.out_size,
+                        req.reply::<ReplyIoctl>().request(arg),
+                    )
+                    .await;
+            }
+            ll::Operation::Poll { arg } => {
+                let ph = match arg.flags & FUSE_POLL_SCHEDULE_NOTIFY {
+                    0 => None,
+                    _ => Some(PollHandle::new(arg.kh, req.ch.clone())),
+                };
+                se.device
+                    .poll(req, arg.fh, ph, arg.flags, req.reply())
+                    .await;
+            }
+
+            // Other operations aren't used by character devices
+            _ => {
+                warn!("Ignoring unsupported CUSE operation: {}", req.request);
+                req.reply::<ReplyEmpty>().error(ENOSYS);
+            }
+        }
+    }
+
     /// Create a reply object for this request that can be passed to the filesystem
     /// implementation and makes sure that a request is replied exactly once
     fn reply<T: Reply>(&self) -> T {
-        Reply::new(self.request.unique(), self.ch)
+        Reply::new(self.request.unique(), self.ch.clone())
+    }
+
+    /// Create a reply object like `reply` for replies whose layout depends on the
+    /// protocol version
+    fn versioned_reply<T: VersionedReply>(&self) -> T {
+        let mut reply: T = self.reply();
+        reply.set_proto_minor(self.proto_minor);
+        reply
     }
 
     /// Returns the unique identifier of this request
@@ -615,4 +967,126 @@ impl Request {
     pub fn pid(&self) -> u32 {
         self.request.pid()
     }
+
+    /// Returns the security context of the inode to create as (name, value) pairs, e.g.
+    /// `("security.selinux", label)`. Set on mknod, mkdir, symlink and create requests if
+    /// the `FUSE_SECURITY_CTX` capability is enabled, empty otherwise.
+    #[inline]
+    pub fn security_context(&self) -> &[(OsString, Vec<u8>)] {
+        self.request.security_context()
+    }
+
+    /// Returns true if the kernel asked to interrupt this request, e.g. because the
+    /// process that triggered it received a signal
+    #[inline]
+    pub fn is_interrupted(&self) -> bool {
+        self.interrupt.is_set()
+    }
+
+    /// Register the given file as backing file for passthrough I/O (Linux only). The
+    /// returned backing id can be passed to `ReplyOpen::opened_passthrough` or
+    /// `ReplyCreate::created_passthrough` to let the kernel serve reads and writes from the
+    /// file directly. Needs the `FUSE_PASSTHROUGH` capability and `CAP_SYS_ADMIN`.
+    pub fn open_backing<F: AsRawFd>(&self, file: &F) -> io::Result<BackingId> {
+        BackingId::open(self.ch.clone(), file)
+    }
+
+    /// Wait until the kernel asks to interrupt this request. Filesystem methods that
+    /// may take a long time can select on this and reply with EINTR once it completes.
+    /// If the request is never interrupted, the returned future never completes.
+    pub async fn interrupted(&self) {
+        self.interrupt.wait().await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Request;
+    use crate::channel::ChannelSender;
+    use crate::interrupt::Interrupts;
+    use fuse_abi::FUSE_KERNEL_MINOR_VERSION;
+    use std::os::unix::io::{FromRawFd, OwnedFd};
+    use std::sync::Arc;
+
+    /// Create a request from the given data with a channel that sends to a pipe and return
+    /// whether a request was created and what was replied
+    fn parsed(data: &[u8]) -> (bool, Vec<u8>) {
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        assert_eq!(
+            unsafe { libc::fcntl(fds[0], libc::F_SETFL, libc::O_NONBLOCK) },
+            0
+        );
+        let fd = Arc::new(unsafe { OwnedFd::from_raw_fd(fds[1]) });
+        let req = Request::new(
+            ChannelSender::from_fd(&fd),
+            data,
+            FUSE_KERNEL_MINOR_VERSION,
+            true,
+        );
+        let mut buf = vec![0u8; 4096];
+        let len = unsafe { libc::read(fds[0], buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+        unsafe {
+            libc::close(fds[0]);
+        }
+        buf.truncate(len.max(0) as usize);
+        (req.is_some(), buf)
+    }
+
+    /// Request header with the given length and opcode
+    fn header(len: u32, opcode: u32) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&len.to_ne_bytes());
+        data.extend_from_slice(&opcode.to_ne_bytes());
+        data.extend_from_slice(&0xdeadbeefu64.to_ne_bytes()); // unique
+        data.extend_from_slice(&[0; 24]); // nodeid, uid, gid, pid, padding
+        data
+    }
+
+    /// Reply header with the given error
+    fn error_reply(err: i32) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&16u32.to_ne_bytes());
+        data.extend_from_slice(&(-err).to_ne_bytes());
+        data.extend_from_slice(&0xdeadbeefu64.to_ne_bytes());
+        data
+    }
+
+    #[test]
+    fn unknown_operation() {
+        assert_eq!(
+            parsed(&header(40, 9999)),
+            (false, error_reply(libc::ENOSYS))
+        );
+    }
+
+    #[test]
+    fn notify_reply_unique() {
+        let interrupts = Interrupts::default();
+        let ch = ChannelSender::closed();
+        let mut getattr =
+            Request::new(ch.clone(), &header(40, 3), FUSE_KERNEL_MINOR_VERSION, true).unwrap();
+        getattr.register(&interrupts);
+        // Retrieve ids are counted separately and may be equal to the unique of a request
+        // in flight, which must stay interruptible
+        let mut data = header(80, 41);
+        data.extend_from_slice(&[0; 40]);
+        let mut notify_reply = Request::new(ch, &data, FUSE_KERNEL_MINOR_VERSION, true).unwrap();
+        assert!(!notify_reply.interruptible());
+        notify_reply.register(&interrupts);
+        interrupts.interrupt(0xdeadbeef, 2);
+        assert!(getattr.is_interrupted());
+    }
+
+    #[test]
+    fn malformed_request() {
+        // Truncated packet
+        assert_eq!(parsed(&header(48, 3)), (false, error_reply(libc::EIO)));
+        // Missing arguments of a setattr request
+        assert_eq!(parsed(&header(40, 4)), (false, error_reply(libc::EINVAL)));
+        // Truncated header, nothing to reply to
+        assert_eq!(parsed(&header(40, 3)[..20]), (false, Vec::new()));
+        // Forget requests are never replied
+        assert_eq!(parsed(&header(40, 2)), (false, Vec::new()));
+    }
 }
@@ -5,16 +5,21 @@
 //! filesystem is mounted, the session loop receives, dispatches and replies to kernel requests
 //! for filesystem operations under its mount point.
 
+use fuse_abi::FUSE_KERNEL_MINOR_VERSION;
 use libc::{EAGAIN, EINTR, ENODEV, ENOENT};
 use log::{error, info};
 use std::ffi::OsString;
 use std::fmt;
 use std::io;
 use std::path::{Path, PathBuf};
-use std::sync::atomic::{AtomicBool, AtomicU32};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering::Relaxed};
 use std::sync::Arc;
+use tokio::task::JoinSet;
 
 use crate::channel::{self, Channel};
+use crate::interrupt::Interrupts;
+use crate::notify::{Notifier, Retrieves};
+use crate::reply::ZeroMessageOpen;
 use crate::request::Request;
 use crate::Filesystem;
 
@@ -34,77 +39,177 @@ pub struct Session<FS: Filesystem + Send + Sync + 'static> {
     pub filesystem: FS,
     /// Communication channel to the kernel driver
     ch: Channel,
+    /// Cloned channels for additional readers
+    clones: Vec<Channel>,
     /// FUSE protocol major version
     pub proto_major: AtomicU32,
-    /// FUSE protocol minor version
+    /// FUSE protocol minor version, negotiated with the kernel on init
     pub proto_minor: AtomicU32,
+    /// True if extended setxattr arguments were negotiated on init (FUSE_SETXATTR_EXT)
+    pub(crate) setxattr_ext: AtomicBool,
     /// True if the filesystem is initialized (init operation done)
     pub initialized: AtomicBool,
     /// True if the filesystem was destroyed (destroy operation done)
     pub destroyed: AtomicBool,
+    /// Requests currently being processed (to handle interrupts)
+    pub(crate) interrupts: Interrupts,
+    /// Retrieve notifications waiting for the kernel's reply
+    pub(crate) retrieves: Arc<Retrieves>,
+    /// Zero-message opens of files (FUSE_NO_OPEN_SUPPORT)
+    pub(crate) no_open: Arc<ZeroMessageOpen>,
+    /// Zero-message opens of directories (FUSE_NO_OPENDIR_SUPPORT)
+    pub(crate) no_opendir: Arc<ZeroMessageOpen>,
 }
 
 impl<FS: Filesystem + Send + Sync + 'static> Session<FS> {
-    /// Create a new session by mounting the given filesystem to the given mountpoint
+    /// Create a new session by mounting the given filesystem to the given mountpoint.
+    /// Must be called within the context of a tokio runtime.
     pub fn new(filesystem: FS, mountpoint: &Path, options: &[OsString]) -> io::Result<Session<FS>> {
         info!("Mounting {}", mountpoint.display());
         Channel::new(mountpoint, options).map(|ch| Session {
-            filesystem: filesystem,
-            ch: ch,
+            filesystem,
+            ch,
+            clones: Vec::new(),
             proto_major: AtomicU32::new(0),
-            proto_minor: AtomicU32::new(0),
+            proto_minor: AtomicU32::new(FUSE_KERNEL_MINOR_VERSION),
+            setxattr_ext: AtomicBool::new(false),
             initialized: AtomicBool::new(false),
             destroyed: AtomicBool::new(false),
+            interrupts: Interrupts::default(),
+            retrieves: Arc::default(),
+            no_open: Arc::default(),
+            no_opendir: Arc::default(),
         })
     }
 
     /// Return path of the mounted filesystem
     pub fn mountpoint(&self) -> &Path {
-        &self.ch.mountpoint()
+        self.ch.mountpoint()
+    }
+
+    /// Return a handle for sending notifications to the kernel driver
+    pub fn notifier(&self) -> Notifier {
+        Notifier::new(self.ch.sender(), self.retrieves.clone())
+    }
+
+    /// Receive requests with the given number of concurrent readers (Linux only). Every
+    /// additional reader receives requests on its own cloned channel to the kernel driver
+    /// and replies are sent on the channel the request was received from. Since each
+    /// reader needs its own buffer (which takes up much memory), a single reader is
+    /// used by default.
+    pub fn set_readers(&mut self, readers: usize) -> io::Result<()> {
+        let clones = readers.saturating_sub(1);
+        while self.clones.len() < clones {
+            self.clones.push(self.ch.try_clone()?);
+        }
+        self.clones.truncate(clones);
+        Ok(())
     }
 
     /// Run the session loop that receives kernel requests and dispatches them to method
-    /// calls into the filesystem. This read-dispatch-loop is non-concurrent to prevent
-    /// having multiple buffers (which take up much memory), but the filesystem methods
-    /// may run concurrent by spawning threads.
-    pub fn run(self) -> io::Result<()> {
-        // Buffer for receiving requests from the kernel. Only one is allocated and
-        // it is reused immediately after dispatching to conserve memory and allocations.
-        let mut buffer: Vec<u8> = Vec::with_capacity(BUFFER_SIZE);
+    /// calls into the filesystem. Every reader (see `set_readers`) runs its own
+    /// read-dispatch-loop and every request is dispatched in its own task, so filesystem
+    /// methods run concurrently. Waiting for requests doesn't block the executor thread.
+    pub async fn run(self) -> io::Result<()> {
         let se = Arc::new(self);
-        loop {
-            // Read the next request from the given channel to kernel driver
-            // The kernel driver makes sure that we get exactly one request per read
-            match se.ch.receive(&mut buffer) {
-                Ok(()) => match Request::new(se.ch.sender(), &buffer) {
-                    // Dispatch request
-                    Some(req) => {
-                        let se = se.clone();
-                        tokio::spawn(async move { req.dispatch(se).await });
+        let mut readers = JoinSet::new();
+        for idx in 0..=se.clones.len() {
+            readers.spawn(Self::receive(se.clone(), idx));
+        }
+        // All readers end once the filesystem is unmounted. If one of them fails,
+        // the others are cancelled.
+        let res = match readers.join_next().await {
+            Some(Ok(res)) => res,
+            Some(Err(err)) => Err(io::Error::other(err)),
+            None => Ok(()),
+        };
+        readers.abort_all();
+        se.retrieves.clear();
+        res
+    }
+
+    /// Read-dispatch-loop of a single reader, using the channel with the given index
+    async fn receive(se: Arc<Self>, idx: usize) -> io::Result<()> {
+        let ch = match idx {
+            0 => &se.ch,
+            n => &se.clones[n - 1],
+        };
+        receive_loop(
+            ch,
+            &se.interrupts,
+            &se.proto_minor,
+            Some(&se.setxattr_ext),
+            |req| {
+                let se = se.clone();
+                tokio::spawn(async move {
+                    let unique = req.interruptible().then(|| req.unique());
+                    req.dispatch(se.clone()).await;
+                    if let Some(unique) = unique {
+                        se.interrupts.unregister(unique);
                     }
-                    // Quit loop on illegal request
-                    None => break,
-                },
-                Err(err) => match err.raw_os_error() {
-                    // Operation interrupted. Accordingly to FUSE, this is safe to retry
-                    Some(ENOENT) => continue,
-                    // Interrupted system call, retry
-                    Some(EINTR) => continue,
-                    // Explicitly try again
-                    Some(EAGAIN) => continue,
-                    // Filesystem was unmounted, quit the loop
-                    Some(ENODEV) => break,
-                    // Unhandled error
-                    _ => return Err(err),
-                },
-            }
+                });
+            },
+        )
+        .await
+    }
+}
+
+/// Read-dispatch-loop on the given channel. Every interruptible request is registered as being
+/// in flight and then passed to the given function, which is expected to spawn a task
+/// dispatching it (and to unregister interruptible requests afterwards). Requests are parsed
+/// as negotiated on init, i.e. with the given protocol version and setxattr arguments (if
+/// the session supports setxattr).
+pub(crate) async fn receive_loop<F: FnMut(Request)>(
+    ch: &Channel,
+    interrupts: &Interrupts,
+    proto_minor: &AtomicU32,
+    setxattr_ext: Option<&AtomicBool>,
+    mut dispatch: F,
+) -> io::Result<()> {
+    // Buffer for receiving requests from the kernel. Only one is allocated per reader and
+    // it is reused immediately after dispatching to conserve memory and allocations.
+    let mut buffer: Vec<u8> = Vec::with_capacity(BUFFER_SIZE);
+    loop {
+        // Read the next request from the given channel to kernel driver
+        // The kernel driver makes sure that we get exactly one request per read
+        match ch.receive(&mut buffer).await {
+            Ok(()) => match Request::new(
+                ch.sender(),
+                &buffer,
+                proto_minor.load(Relaxed),
+                setxattr_ext.is_some_and(|ext| ext.load(Relaxed)),
+            ) {
+                // Dispatch request
+                Some(mut req) => {
+                    req.register(interrupts);
+                    dispatch(req);
+                }
+                // Skip illegal request (it was already replied with an error)
+                None => continue,
+            },
+            Err(err) => match err.raw_os_error() {
+                // Operation interrupted. Accordingly to FUSE, this is safe to retry
+                Some(ENOENT) => continue,
+                // Interrupted system call, retry
+                Some(EINTR) => continue,
+                // Explicitly try again
+                Some(EAGAIN) => continue,
+                // Filesystem was unmounted, quit the loop
+                Some(ENODEV) => break,
+                // Unhandled error
+                _ => return Err(err),
+            },
         }
-        Ok(())
     }
+    Ok(())
 }
 
 impl<FS: Filesystem + Send + Sync + 'static> Session<FS> {
-    /// Run the session loop in a background thread
+    /// Run the session loop in a background task
+    ///
+    /// # Safety
+    ///
+    /// See [`BackgroundSession::new`].
     pub unsafe fn spawn(self) -> io::Result<BackgroundSession> {
         BackgroundSession::new(self)
     }
@@ -120,31 +225,45 @@ impl<FS: Filesystem + Send + Sync + 'static> Drop for Session<FS> {
 pub struct BackgroundSession {
     /// Path of the mounted filesystem
     pub mountpoint: PathBuf,
+    /// Handle for sending notifications to the kernel driver
+    notifier: Notifier,
     /// handle of the background session
     pub handle: tokio::task::JoinHandle<Result<(), std::io::Error>>,
 }
 
 impl BackgroundSession {
     /// Create a new background session for the given session by running its
-    /// session loop in a background thread. If the returned handle is dropped,
+    /// session loop in a background task. If the returned handle is dropped,
     /// the filesystem is unmounted and the given session ends.
+    ///
+    /// # Safety
+    ///
+    /// The session loop is spawned onto the current tokio runtime, so this must be called
+    /// from within a runtime context.
     pub unsafe fn new<FS: Filesystem + Send + Sync + 'static>(
         se: Session<FS>,
     ) -> io::Result<BackgroundSession> {
         let mountpoint = se.mountpoint().to_path_buf();
-        let handle = tokio::spawn(async move { se.run() });
+        let notifier = se.notifier();
+        let handle = tokio::spawn(se.run());
         Ok(BackgroundSession {
-            mountpoint: mountpoint,
-            handle: handle,
+            mountpoint,
+            notifier,
+            handle,
         })
     }
+
+    /// Return a handle for sending notifications to the kernel driver
+    pub fn notifier(&self) -> Notifier {
+        self.notifier.clone()
+    }
 }
 
 impl Drop for BackgroundSession {
     fn drop(&mut self) {
         info!("Unmounting {}", self.mountpoint.display());
         // Unmounting the filesystem will eventually end the session loop,
-        // drop the session and hence end the background thread.
+        // drop the session and hence end the background task.
         match channel::unmount(&self.mountpoint) {
             Ok(()) => (),
             Err(err) => error!("Failed to unmount {}: {}", self.mountpoint.display(), err),